@@ -1,52 +1,164 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tokio::time::sleep;
 
-use crate::db::DbMap;
-use crate::logger::log_info;
+use crate::db::{unix_millis, DbInstance, DbMap, SHARD_COUNT};
+use crate::logger::log_info_with;
+use crate::pubsub::{SharedPubSub, EXPIRED_CHANNEL};
+
+/// Time between two cleanup cycles unless configured otherwise
+pub const DEFAULT_CLEAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Databases with at most this many keys are swept in full instead of sampled
+const FULL_SWEEP_MAX_KEYS: usize = 1000;
+
+/// Keys looked at per shard in each sampling pass
+const SAMPLE_SIZE: usize = 20;
+
+/// Longest a cleanup cycle keeps sampling a single database
+const SAMPLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+/// Time between two flushes of changed databases to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Unix timestamp (in milliseconds) of the cleaner's next scheduled sweep,
+/// published by the cleaner task so connections can observe it.
+pub type NextCleanAt = Arc<AtomicU64>;
+
+/// Returns the number of whole seconds (rounded up) until the next sweep.
+pub fn seconds_until_next_clean(next_clean_at: &NextCleanAt) -> u64 {
+    let remaining_ms = next_clean_at.load(Ordering::Relaxed).saturating_sub(unix_millis());
+    remaining_ms.div_ceil(1000)
+}
+
+/// Clones the handles of every database in `db_map`, holding the global
+/// lock only for the copy so `create`, `use` and `drop` aren't blocked
+/// while each database is processed.
+fn snapshot(db_map: &DbMap) -> Vec<Arc<DbInstance>> {
+    db_map.lock().unwrap().values().cloned().collect()
+}
+
+/// Logs the expired keys removed from `db_instance`, announces each one on
+/// the `expired` channel and saves the database. Returns how many there were.
+fn report_expired(db_instance: &DbInstance, expired_keys: Vec<String>, pubsub: &SharedPubSub) -> usize {
+    let removed = expired_keys.len();
+    // Nothing changed, so there is nothing to save either
+    if removed == 0 {
+        return 0;
+    }
+
+    log_info_with(
+        &format!(
+            "🧼 Cleaned {} expired keys from '{}': [{}]",
+            removed,
+            db_instance.name,
+            expired_keys.join(", ")
+        ),
+        &[("database", &db_instance.name)],
+    );
+    for key in expired_keys {
+        pubsub.publish(EXPIRED_CHANNEL, format!("{} {}", db_instance.name, key));
+    }
+    db_instance.persist();
+    removed
+}
+
+/// Scans all databases in `db_map` once and removes expired keys, announcing
+/// each one on the `expired` channel. Returns the total number of keys removed.
+pub fn sweep_expired(db_map: &DbMap, pubsub: &SharedPubSub) -> usize {
+    snapshot(db_map)
+        .iter()
+        .map(|db_instance| report_expired(db_instance, db_instance.remove_expired(), pubsub))
+        .sum()
+}
+
+/// Removes expired keys from a database without necessarily looking at all
+/// of them. Small databases are swept in full; larger ones are sampled the
+/// way Redis does it: each pass looks at a few keys per shard, and passes
+/// repeat while more than a quarter of the sampled keys with a TTL had
+/// expired, since more are then likely left. Keys the sampling misses are
+/// found by a later cycle, or expire lazily when accessed.
+fn sample_expired(db_instance: &DbInstance) -> Vec<String> {
+    if db_instance.data.read_all().len() <= FULL_SWEEP_MAX_KEYS {
+        return db_instance.remove_expired();
+    }
+
+    let started = Instant::now();
+    let mut expired_keys = Vec::new();
+    loop {
+        let (mut with_ttl, mut expired) = (0, 0);
+        for shard in 0..SHARD_COUNT {
+            let (sampled, removed) = db_instance.remove_expired_sample(shard, SAMPLE_SIZE);
+            with_ttl += sampled;
+            expired += removed.len();
+            expired_keys.extend(removed);
+        }
+        if expired * 4 <= with_ttl || started.elapsed() >= SAMPLE_TIME_LIMIT {
+            return expired_keys;
+        }
+    }
+}
+
+/// Starts a background async task that removes expired keys from all
+/// databases in `db_map` every interval, sampling the large ones. A new
+/// interval sent on `intervals` reschedules the next cycle from now.
+pub async fn start_cleaner(
+    db_map: DbMap,
+    next_clean_at: NextCleanAt,
+    mut intervals: watch::Receiver<Duration>,
+    pubsub: SharedPubSub,
+) {
+    let mut interval = *intervals.borrow_and_update();
+    // The first cycle runs right away, but publish the one after it before
+    // the task starts so NEXTCLEAN never reports a sweep that isn't scheduled
+    next_clean_at.store(unix_millis() + interval.as_millis() as u64, Ordering::Relaxed);
 
-/// Starts a background async task that periodically scans all databases
-/// in `db_map` and removes expired keys every 5 seconds.
-pub async fn start_cleaner(db_map: DbMap) {
     // Spawn a new asynchronous task to run in the background
     tokio::spawn(async move {
         loop {
-            {
-                // Acquire a lock on the global database map
-                let db_map_lock = db_map.lock().unwrap();
-
-                // Iterate over each database instance
-                for (db_name, db_instance) in db_map_lock.iter() {
-                    // Lock the actual key-value data inside the database
-                    let mut data_lock = db_instance.data.lock().unwrap();
-
-                    // Collect all keys that have expired
-                    let expired_keys: Vec<String> = data_lock
-                        .iter()
-                        .filter(|(_, v)| v.is_expired()) // Check if value is expired
-                        .map(|(k, _)| k.clone()) // Collect the key
-                        .collect();
-
-                    // Remove all expired keys from the database
-                    for key in &expired_keys {
-                        data_lock.remove(key);
-                    }
+            for db_instance in snapshot(&db_map) {
+                report_expired(&db_instance, sample_expired(&db_instance), &pubsub);
+            }
 
-                    // Log the cleanup action if any keys were removed
-                    if !expired_keys.is_empty() {
-                        log_info(&format!(
-                            "🧼 Cleaned {} expired keys from '{}': [{}]",
-                            expired_keys.len(),
-                            db_name,
-                            expired_keys.join(", ")
-                        ));
+            // Publish when the next cycle will run, then sleep until then
+            next_clean_at.store(
+                unix_millis() + interval.as_millis() as u64,
+                Ordering::Relaxed,
+            );
+            loop {
+                tokio::select! {
+                    _ = sleep(interval) => break,
+                    Ok(()) = intervals.changed() => {
+                        interval = *intervals.borrow_and_update();
+                        next_clean_at.store(
+                            unix_millis() + interval.as_millis() as u64,
+                            Ordering::Relaxed,
+                        );
                     }
-                    drop(data_lock);
-                    db_instance.persist();
                 }
             }
+        }
+    });
+}
 
-            // Sleep for 5 seconds before the next cleanup cycle
-            sleep(Duration::from_secs(5)).await;
+/// Saves every database in `db_map` that changed since its last save.
+pub fn flush_all(db_map: &DbMap) {
+    for db_instance in snapshot(db_map) {
+        db_instance.flush();
+    }
+}
+
+/// Starts a background async task that writes changed databases to disk
+/// every second, so bursts of writes are coalesced into one save.
+pub async fn start_flusher(db_map: DbMap) {
+    tokio::spawn(async move {
+        loop {
+            sleep(FLUSH_INTERVAL).await;
+            // Saves block on disk, so run them on the blocking thread pool
+            let db_map = db_map.clone();
+            let _ = tokio::task::spawn_blocking(move || flush_all(&db_map)).await;
         }
     });
 }
@@ -1,24 +1,567 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::path::Path;
-use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::logger::log_info;
+use crate::logger::{log_debug, log_enabled, log_error_with, log_info, log_info_with, log_warn, LogLevel};
 
-// Type alias for a database: a thread-safe, shared, mutable map of key-value pairs.
-pub type Db = Arc<Mutex<HashMap<String, ValueWithExpiry>>>;
+/// Number of independently locked shards each database's keys are split
+/// across. More shards let more writes to disjoint keys run in parallel.
+pub const SHARD_COUNT: usize = 16;
+
+// Type alias for one shard: a map of key-value pairs.
+pub type Shard = HashMap<String, ValueWithExpiry>;
 
 // Type alias for managing multiple databases: each identified by a name and associated with a `DbInstance`.
-pub type DbMap = Arc<Mutex<HashMap<String, DbInstance>>>;
+// The instance in this map is the only one for its database; every session
+// and background task holds an `Arc` to it, so all of them see the same keys,
+// credentials and settings. Replacing the entry (drop, create) detaches
+// sessions still holding the old instance.
+pub type DbMap = Arc<Mutex<HashMap<String, Arc<DbInstance>>>>;
 
-/// Represents a single database instance.
-#[derive(Debug, Clone)]
+/// Unix timestamp (in milliseconds) until which expiry is suspended server-wide.
+/// While paused, `is_expired` reports every key as live, so both lazy expiry
+/// and the cleaner leave expired keys in place (and GET serves stale values).
+static EXPIRY_PAUSED_UNTIL: AtomicU64 = AtomicU64::new(0);
+
+/// Directory database files are stored in unless configured otherwise
+pub const DEFAULT_DATA_DIR: &str = "dbs";
+
+/// Directory database files are stored in, set once at startup.
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory database files are stored in. Only the first call
+/// has an effect, so it must happen before any database is loaded.
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+/// Returns the directory database files are stored in.
+pub fn data_dir() -> &'static Path {
+    DATA_DIR
+        .get()
+        .map(PathBuf::as_path)
+        .unwrap_or(Path::new(DEFAULT_DATA_DIR))
+}
+
+/// Longest database name accepted by `validate_db_name`
+const MAX_DB_NAME_LEN: usize = 64;
+
+/// Checks that `name` can be used as a database name. Names become file
+/// names in the data directory, so only letters, digits, `-` and `_` are
+/// allowed; anything else, such as `/` or `..`, could point outside it.
+pub fn validate_db_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_DB_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!(
+            "Error: Invalid database name '{}', use up to {} letters, digits, '-' and '_'",
+            name, MAX_DB_NAME_LEN
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the path of the file the database `name` is stored in.
+pub fn db_path(name: &str) -> PathBuf {
+    data_dir().join(format!("{}.json", name))
+}
+
+/// Returns the path of the write-ahead log of the database `name`.
+pub fn wal_path(name: &str) -> PathBuf {
+    data_dir().join(format!("{}.wal", name))
+}
+
+/// Whether changes are appended to a write-ahead log, set once at startup.
+static WAL_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns the write-ahead log on or off for databases loaded or created
+/// afterwards, so it must be called before any database is loaded.
+pub fn set_wal_enabled(enabled: bool) {
+    WAL_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Most keys a database may hold before the least recently used ones are
+/// evicted (0 = unlimited), set once at startup.
+static MAX_KEYS: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets or clears (`None`) the number of keys each database may hold.
+pub fn set_max_keys(limit: Option<usize>) {
+    MAX_KEYS.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Returns the key limit of every database, if one is set.
+fn max_keys() -> Option<usize> {
+    match MAX_KEYS.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Most bytes of keys and values a database may hold before the least
+/// recently used keys are evicted (0 = unlimited), set once at startup.
+static MAX_MEMORY: AtomicU64 = AtomicU64::new(0);
+
+/// Sets or clears (`None`) the memory limit of every database. Usage is only
+/// tracked while a limit is set, so it must be called before any database
+/// is loaded.
+pub fn set_max_memory(limit: Option<u64>) {
+    MAX_MEMORY.store(limit.unwrap_or(0), Ordering::Relaxed);
+}
+
+/// Returns the memory limit of every database, if one is set.
+fn max_memory() -> Option<u64> {
+    match MAX_MEMORY.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Returns true if keys may be evicted, so their use has to be tracked.
+fn eviction_enabled() -> bool {
+    max_keys().is_some() || max_memory().is_some()
+}
+
+/// Approximate number of bytes used by `key` and its value.
+fn entry_size(key: &str, val: &ValueWithExpiry) -> u64 {
+    (key.len() + val.value.size()) as u64
+}
+
+/// Logical clock ticked whenever a key is read or written, so the least
+/// recently used key is the one with the lowest stamp.
+static ACCESS_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+/// When a key was last read or written, as a tick of `ACCESS_CLOCK`. Atomic
+/// so reads can update it under a shard's read lock.
+#[derive(Debug, Default)]
+struct LastAccess(AtomicU64);
+
+impl LastAccess {
+    fn now() -> Self {
+        Self(AtomicU64::new(ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    fn touch(&self) {
+        self.0.store(ACCESS_CLOCK.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Clone for LastAccess {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.get()))
+    }
+}
+
+/// Returns the current unix time in milliseconds.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Converts a wall-clock deadline into an `Instant` on the monotonic clock.
+/// Deadlines in the past map to the current instant.
+fn instant_for(deadline: SystemTime) -> Instant {
+    let remaining = deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO);
+    Instant::now() + remaining
+}
+
+/// Stable 64-bit FNV-1a hash of a key. Decides which shard a key lives in,
+/// and is exposed by SHARD so clients can reproduce the mapping.
+pub fn fnv1a_64(key: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    key.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+/// Returns a pseudo-random number below `bound`, which must not be zero.
+/// Every `RandomState` is seeded differently, which is random enough for
+/// picking where to sample keys.
+fn random_below(bound: usize) -> usize {
+    (RandomState::new().hash_one(unix_millis()) % bound as u64) as usize
+}
+
+/// Position of `key` in the order SCAN walks a database: by shard, then by
+/// hash. The shard is the hash's low bits, so rotating them to the top gives
+/// that order as a single number. Keys never change position.
+fn scan_position(key: &str) -> u64 {
+    fnv1a_64(key).rotate_right(SHARD_COUNT.trailing_zeros())
+}
+
+/// Returns the index of the shard `key` is stored in.
+fn shard_index(key: &str) -> usize {
+    (fnv1a_64(key) % SHARD_COUNT as u64) as usize
+}
+
+/// A database's keys, split across `SHARD_COUNT` maps by key hash, each
+/// behind its own lock. Read-only commands share a shard's read lock;
+/// anything that modifies takes the write lock.
+#[derive(Debug)]
+pub struct ShardedMap {
+    shards: Vec<RwLock<Shard>>,
+    // Bytes of keys and values in each shard, expired ones included, for
+    // the memory limit and database quotas
+    used: Vec<AtomicU64>,
+    // Log that write guards append changed keys to, if enabled
+    wal: Option<Wal>,
+}
+
+/// Shards locked together by a single command.
+pub type ReadGuard<'a> = ShardGuard<'a, RwLockReadGuard<'a, Shard>>;
+pub type WriteGuard<'a> = ShardGuard<'a, RwLockWriteGuard<'a, Shard>>;
+
+impl Default for ShardedMap {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::new(HashMap::new())).collect(),
+            used: (0..SHARD_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            wal: None,
+        }
+    }
+}
+
+impl FromIterator<(String, ValueWithExpiry)> for ShardedMap {
+    fn from_iter<I: IntoIterator<Item = (String, ValueWithExpiry)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        for (key, val) in iter {
+            let index = shard_index(&key);
+            *map.used[index].get_mut() += entry_size(&key, &val);
+            map.shards[index].get_mut().unwrap().insert(key, val);
+        }
+        map
+    }
+}
+
+impl ShardedMap {
+    /// Read-locks the shards holding `keys`.
+    pub fn read<K: AsRef<str>>(&self, keys: &[K]) -> ReadGuard<'_> {
+        self.lock(Self::shards_of(keys), None, |shard| shard.read().unwrap())
+    }
+
+    /// Read-locks every shard, for commands that scan the whole database.
+    pub fn read_all(&self) -> ReadGuard<'_> {
+        self.lock([true; SHARD_COUNT], None, |shard| shard.read().unwrap())
+    }
+
+    /// Write-locks the shards holding `keys`.
+    pub fn write<K: AsRef<str>>(&self, keys: &[K]) -> WriteGuard<'_> {
+        self.lock(Self::shards_of(keys), self.wal.as_ref(), |shard| shard.write().unwrap())
+    }
+
+    /// Write-locks every shard, for commands that rewrite the whole database.
+    pub fn write_all(&self) -> WriteGuard<'_> {
+        self.lock([true; SHARD_COUNT], self.wal.as_ref(), |shard| shard.write().unwrap())
+    }
+
+    /// Starts logging changes to the write-ahead log of the database `name`
+    /// if the log is enabled.
+    fn with_wal(mut self, name: &str) -> Self {
+        if WAL_ENABLED.load(Ordering::Relaxed) {
+            self.wal = Some(Wal::new(wal_path(name)));
+        }
+        self
+    }
+
+    /// Approximate number of bytes used by the keys and values of every
+    /// shard, expired ones included.
+    fn memory_used(&self) -> u64 {
+        self.used.iter().map(|used| used.load(Ordering::Relaxed)).sum()
+    }
+
+    fn shards_of<K: AsRef<str>>(keys: &[K]) -> [bool; SHARD_COUNT] {
+        let mut wanted = [false; SHARD_COUNT];
+        for key in keys {
+            wanted[shard_index(key.as_ref())] = true;
+        }
+        wanted
+    }
+
+    fn lock<'a, G: Deref<Target = Shard>>(
+        &'a self,
+        wanted: [bool; SHARD_COUNT],
+        wal: Option<&'a Wal>,
+        lock: impl Fn(&'a RwLock<Shard>) -> G,
+    ) -> ShardGuard<'a, G> {
+        // Shards are always locked in index order, so two commands locking
+        // overlapping sets of shards can never deadlock
+        ShardGuard {
+            shards: self
+                .shards
+                .iter()
+                .zip(wanted)
+                .map(|(shard, wanted)| wanted.then(|| lock(shard)))
+                .collect(),
+            used: &self.used,
+            sizes_before: HashMap::new(),
+            wal,
+            changed: HashSet::new(),
+        }
+    }
+}
+
+/// A set of locked shards, accessed like a single map. Keys must belong
+/// to one of the locked shards.
+pub struct ShardGuard<'a, G: Deref<Target = Shard>> {
+    shards: Vec<Option<G>>,
+    // Byte counts of the shards, updated when the guard is dropped
+    used: &'a [AtomicU64],
+    // Size of each changed key before its first change
+    sizes_before: HashMap<String, u64>,
+    // Write-ahead log the changed keys are appended to when the guard is dropped
+    wal: Option<&'a Wal>,
+    // Keys that may have changed, only tracked while there is a log
+    changed: HashSet<String>,
+}
+
+impl<G: Deref<Target = Shard>> ShardGuard<'_, G> {
+    fn shard(&self, key: &str) -> &Shard {
+        self.shards[shard_index(key)]
+            .as_deref()
+            .expect("key's shard is not locked")
+    }
+
+    /// Looks up a key, marking it as used for LRU eviction.
+    pub fn get(&self, key: &str) -> Option<&ValueWithExpiry> {
+        let val = self.shard(key).get(key);
+        if let Some(val) = val
+            && eviction_enabled()
+        {
+            val.last_access.touch();
+        }
+        val
+    }
+
+    /// Number of keys in the locked shards, including expired ones.
+    pub fn len(&self) -> usize {
+        self.shards.iter().flatten().map(|shard| shard.len()).sum()
+    }
+
+    /// Iterates over the keys of every locked shard.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ValueWithExpiry)> {
+        self.shards.iter().flatten().flat_map(|shard| shard.iter())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &ValueWithExpiry> {
+        self.iter().map(|(_, val)| val)
+    }
+}
+
+impl<G: DerefMut<Target = Shard>> ShardGuard<'_, G> {
+    fn shard_mut(&mut self, key: &str) -> &mut Shard {
+        if self.wal.is_some() && !self.changed.contains(key) {
+            self.changed.insert(key.to_string());
+        }
+        if !self.sizes_before.contains_key(key) {
+            let before = self.shard(key).get(key).map_or(0, |val| entry_size(key, val));
+            self.sizes_before.insert(key.to_string(), before);
+        }
+        self.shards[shard_index(key)]
+            .as_deref_mut()
+            .expect("key's shard is not locked")
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut ValueWithExpiry> {
+        let val = self.shard_mut(key).get_mut(key);
+        if let Some(val) = &val
+            && eviction_enabled()
+        {
+            val.last_access.touch();
+        }
+        val
+    }
+
+    pub fn insert(&mut self, key: String, val: ValueWithExpiry) -> Option<ValueWithExpiry> {
+        // A copied entry carries its source's stamp, but the write is a use too
+        val.last_access.touch();
+        self.shard_mut(&key).insert(key, val)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<ValueWithExpiry> {
+        self.shard_mut(key).remove(key)
+    }
+}
+
+impl<G: Deref<Target = Shard>> ShardGuard<'_, G> {
+    /// Bytes used by the keys and values of every shard, counting the
+    /// changes made through this guard so far.
+    fn memory_used(&self) -> u64 {
+        let total: u64 = self.used.iter().map(|used| used.load(Ordering::Relaxed)).sum();
+        self.sizes_before.iter().fold(total, |total, (key, before)| {
+            let after = self.shard(key).get(key).map_or(0, |val| entry_size(key, val));
+            (total + after).saturating_sub(*before)
+        })
+    }
+
+    /// Brings the byte counts of the locked shards up to date with the
+    /// changes made through this guard.
+    fn account_memory(&self) {
+        for (key, before) in &self.sizes_before {
+            let after = self.shard(key).get(key).map_or(0, |val| entry_size(key, val));
+            let used = &self.used[shard_index(key)];
+            used.store((used.load(Ordering::Relaxed) + after).saturating_sub(*before), Ordering::Relaxed);
+        }
+    }
+}
+
+impl<G: Deref<Target = Shard>> Drop for ShardGuard<'_, G> {
+    /// Logs the final state of every changed key before the shards are
+    /// unlocked, so the log orders changes to a key the way they happened.
+    fn drop(&mut self) {
+        self.account_memory();
+
+        let Some(wal) = self.wal else {
+            return;
+        };
+
+        let records: Vec<WalRecord> = self
+            .changed
+            .iter()
+            .map(|key| match self.get(key) {
+                Some(val) => WalRecord::Set { key: key.clone(), entry: val.into() },
+                None => WalRecord::Del { key: key.clone() },
+            })
+            .collect();
+        if records.is_empty() {
+            return;
+        }
+
+        if let Err(e) = wal.append(&records) {
+            log_error_with(
+                &format!("⚠️ Failed to append to write-ahead log '{}': {}", wal.path.display(), e),
+                &[("wal", &wal.path.to_string_lossy())],
+            );
+        }
+    }
+}
+
+/// A change to one key, as stored in the write-ahead log.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum WalRecord {
+    // The key now holds `entry`
+    Set { key: String, entry: SerializableValueWithExpiry },
+    // The key no longer exists
+    Del { key: String },
+}
+
+/// Append-only log of the keys changed since the database file was last
+/// written. Each record is fsynced before the command that made it
+/// replies, and the log is cleared once a snapshot includes its records.
+#[derive(Debug)]
+struct Wal {
+    path: PathBuf,
+    // Opened on the first append and kept open until the log is cleared
+    file: Mutex<Option<File>>,
+}
+
+impl Wal {
+    fn new(path: PathBuf) -> Self {
+        Self { path, file: Mutex::new(None) }
+    }
+
+    /// Appends `records`, one JSON object per line, and fsyncs the log.
+    fn append(&self, records: &[WalRecord]) -> io::Result<()> {
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record).map_err(io::Error::other)?);
+            lines.push('\n');
+        }
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            let created = !self.path.exists();
+            *file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+            // Make the new log's directory entry durable too
+            if created {
+                File::open(data_dir())?.sync_all()?;
+            }
+        }
+        let file = file.as_mut().expect("log file was just opened");
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Deletes the log. The next append starts a new one.
+    fn clear(&self) -> io::Result<()> {
+        *self.file.lock().unwrap() = None;
+        remove_wal_file(&self.path)
+    }
+}
+
+/// Deletes the write-ahead log at `path`, if there is one.
+fn remove_wal_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Applies the records of the write-ahead log at `path` to `data` and
+/// returns how many were applied. A record torn by a crash ends the replay.
+fn replay_wal(path: &Path, data: &mut HashMap<String, ValueWithExpiry>) -> usize {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return 0;
+    };
+
+    let mut applied = 0;
+    for line in contents.lines() {
+        match serde_json::from_str::<WalRecord>(line) {
+            Ok(WalRecord::Set { key, entry }) => {
+                data.insert(key, entry.into());
+            }
+            Ok(WalRecord::Del { key }) => {
+                data.remove(&key);
+            }
+            Err(e) => {
+                log_warn(&format!(
+                    "⚠️ Ignoring the rest of write-ahead log '{}' after record {}: {}",
+                    path.display(),
+                    applied + 1,
+                    e
+                ));
+                break;
+            }
+        }
+        applied += 1;
+    }
+    applied
+}
+
+/// Suspends key expiry for the given duration. Expiry resumes automatically.
+pub fn pause_expiry(duration: Duration) {
+    let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    EXPIRY_PAUSED_UNTIL.store(unix_millis().saturating_add(millis), Ordering::Relaxed);
+}
+
+/// Returns true while an expiry pause is in effect.
+pub fn expiry_paused() -> bool {
+    unix_millis() < EXPIRY_PAUSED_UNTIL.load(Ordering::Relaxed)
+}
+
+/// Represents a single database instance. It isn't `Clone`: share it
+/// through the `Arc` held in `DbMap` instead.
+#[derive(Debug)]
 pub struct DbInstance {
     // The actual data in the DB, stored with expiration support.
-    pub data: Db,
+    pub data: ShardedMap,
     // Whether authentication is required to use this database.
     pub require_auth: bool,
     // Optional username for authentication.
@@ -27,13 +570,155 @@ pub struct DbInstance {
     pub password: Option<String>,
     // Database name
     pub name: String,
+    // Set when the data changed since the last save.
+    dirty: AtomicBool,
+    // Tracks persistence failures.
+    persist_health: PersistHealth,
+    // Maximum bytes of keys and values the database may hold (0 = unlimited).
+    quota: AtomicU64,
+    // Seconds until keys written without a TTL expire (0 = never).
+    default_ttl: AtomicU64,
+    // Shared by every statement and held exclusively by EXEC, so a
+    // transaction's statements never interleave with other connections'.
+    exec_gate: RwLock<()>,
+    // Set once the database is dropped, after which it is never saved again
+    // and sessions still holding it deselect it.
+    dropped: AtomicBool,
+    // Held while the database file is written or deleted, so a save that
+    // is under way can't put the file back after a drop removed it.
+    file_lock: Mutex<()>,
+}
+
+/// Consecutive persist failures before persistence is suspended
+const PERSIST_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long persistence stays suspended once the threshold is hit
+const PERSIST_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Circuit breaker state for `DbInstance::persist`.
+#[derive(Debug, Default)]
+struct PersistHealth {
+    // Number of failed saves since the last successful one
+    consecutive_failures: AtomicU32,
+    // Unix timestamp (ms) before which no save is attempted
+    retry_after: AtomicU64,
+}
+
+// Serializable version of Value. Strings stay plain JSON strings so files
+// written before other value kinds existed still load.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum SerializableValue {
+    String(String),
+    List { list: Vec<String> },
+    Hash { hash: HashMap<String, String> },
+    Set { set: Vec<String> },
+    Bytes {
+        #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+        bytes: Vec<u8>,
+    },
+}
+
+/// Writes binary values as base64, since JSON strings must be text.
+fn serialize_base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bytes))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    BASE64.decode(encoded).map_err(serde::de::Error::custom)
+}
+
+impl From<&Value> for SerializableValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::String(s) => SerializableValue::String(s.clone()),
+            Value::List(list) => SerializableValue::List { list: list.iter().cloned().collect() },
+            Value::Hash(hash) => SerializableValue::Hash { hash: hash.clone() },
+            Value::Set(set) => {
+                // Sorted so saving an unchanged set doesn't reorder the file
+                let mut members: Vec<String> = set.iter().cloned().collect();
+                members.sort();
+                SerializableValue::Set { set: members }
+            }
+            Value::Bytes(bytes) => SerializableValue::Bytes { bytes: bytes.clone() },
+        }
+    }
+}
+
+impl From<SerializableValue> for Value {
+    fn from(value: SerializableValue) -> Self {
+        match value {
+            SerializableValue::String(s) => Value::String(s),
+            SerializableValue::List { list } => Value::List(list.into()),
+            SerializableValue::Hash { hash } => Value::Hash(hash),
+            SerializableValue::Set { set } => Value::Set(set.into_iter().collect()),
+            SerializableValue::Bytes { bytes } => Value::Bytes(bytes),
+        }
+    }
 }
 
 // Serializable version of ValueWithExpiry for JSON storage
 #[derive(Serialize, Deserialize, Debug)]
 struct SerializableValueWithExpiry {
-    value: String,
-    expires_at: Option<u64>, // Stored as timestamp in seconds
+    value: SerializableValue,
+    #[serde(default)]
+    expires_at_unix: Option<u64>, // Absolute unix timestamp in seconds
+    // Legacy files stored seconds remaining at save time; read only
+    #[serde(default, skip_serializing)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    created_at: u64, // Unix timestamp in seconds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+impl From<&ValueWithExpiry> for SerializableValueWithExpiry {
+    fn from(val: &ValueWithExpiry) -> Self {
+        // Instants are process-local, so store the wall-clock deadline instead
+        let expires_at_unix = val.expires_at_wall.map(|deadline| {
+            deadline
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis().div_ceil(1000) as u64)
+                .unwrap_or(0)
+        });
+
+        SerializableValueWithExpiry {
+            value: (&val.value).into(),
+            expires_at_unix,
+            expires_at: None,
+            created_at: val
+                .created_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            tags: val.tags.clone(),
+        }
+    }
+}
+
+impl From<SerializableValueWithExpiry> for ValueWithExpiry {
+    fn from(val: SerializableValueWithExpiry) -> Self {
+        // Rebuild the monotonic deadline from the absolute timestamp so the
+        // remaining lifetime is correct regardless of when we restarted
+        let deadline = match (val.expires_at_unix, val.expires_at) {
+            (Some(ts), _) => Some(UNIX_EPOCH + Duration::from_secs(ts)),
+            // Legacy relative value: the best we can do is count from now
+            (None, Some(remaining)) => Some(SystemTime::now() + Duration::from_secs(remaining)),
+            (None, None) => None,
+        };
+
+        let mut entry = ValueWithExpiry {
+            value: val.value.into(),
+            expires_at: None,
+            expires_at_wall: None,
+            created_at: UNIX_EPOCH + Duration::from_secs(val.created_at),
+            tags: val.tags,
+            last_access: LastAccess::now(),
+        };
+        entry.set_deadline(deadline);
+        entry
+    }
 }
 
 // Serializable version of database for JSON storage
@@ -43,31 +728,89 @@ struct SerializableDb {
     require_auth: bool,
     username: Option<String>,
     password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quota: Option<u64>,
+    // In seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_ttl: Option<u64>,
+}
+
+/// Contents of a database as exchanged by DUMP and RESTORE: the keys of its
+/// file without the credentials. Database files are accepted by RESTORE too,
+/// since their other fields are ignored.
+#[derive(Serialize, Deserialize)]
+struct DumpedDb {
+    data: HashMap<String, SerializableValueWithExpiry>,
+}
+
+/// The part of a database file needed to list it without loading its data
+#[derive(Deserialize)]
+struct StoredDbHeader {
+    require_auth: bool,
+}
+
+/// Returns the names of the databases stored in the data directory, paired
+/// with whether each requires authentication. Unreadable files are skipped.
+pub fn stored_databases() -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(data_dir()) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            let contents = fs::read_to_string(&path).ok()?;
+            let header: StoredDbHeader = serde_json::from_str(&contents).ok()?;
+            Some((name, header.require_auth))
+        })
+        .collect()
+}
+
+/// Quotes a CSV field if it contains a comma, quote or line break, doubling
+/// the quotes inside it (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
 }
 
 impl DbInstance {
     /// Creates a new database instance and persists it to a file.
     pub fn new(name: String, require_auth: bool, username: Option<String>, password: Option<String>) -> Self {
-        // Create the dbs directory if it doesn't exist
-        fs::create_dir_all("dbs").unwrap_or_else(|_| ());
+        // Create the data directory if it doesn't exist
+        fs::create_dir_all(data_dir()).unwrap_or(());
         
         let instance = Self {
-            data: Arc::new(Mutex::new(HashMap::new())),
+            data: ShardedMap::default().with_wal(&name),
             require_auth,
             username,
             password,
             name,
+            dirty: AtomicBool::new(false),
+            persist_health: PersistHealth::default(),
+            quota: AtomicU64::new(0),
+            default_ttl: AtomicU64::new(0),
+            exec_gate: RwLock::new(()),
+            dropped: AtomicBool::new(false),
+            file_lock: Mutex::new(()),
         };
         
-        // Save empty database to file
+        // Save empty database to file, which also clears a stale log left by
+        // a dropped database of the same name
         instance.save_to_file().expect("Failed to save new database");
         instance
     }
 
-    /// Loads a database from file
+    /// Loads a database from file and replays its write-ahead log on top
     pub fn load_from_file(name: &str) -> Option<Self> {
-        let path = format!("dbs/{}.json", name);
-        if !Path::new(&path).exists() {
+        let path = db_path(name);
+        if !path.exists() {
             return None;
         }
 
@@ -77,51 +820,76 @@ impl DbInstance {
         
         let serialized: SerializableDb = serde_json::from_str(&contents).ok()?;
         
-        let mut data = HashMap::new();
-        for (key, val) in serialized.data {
-            let expires_at = val.expires_at.map(|ts| {
-                Instant::now() + Duration::from_secs(ts.saturating_sub(
-                    Instant::now().elapsed().as_secs()
-                ))
-            });
-            
-            data.insert(key, ValueWithExpiry {
-                value: val.value,
-                expires_at,
-            });
+        let mut data: HashMap<String, ValueWithExpiry> = serialized
+            .data
+            .into_iter()
+            .map(|(key, val)| (key, val.into()))
+            .collect();
+
+        // Changes made after the file was last written
+        let replayed = replay_wal(&wal_path(name), &mut data);
+        if replayed > 0 {
+            log_info_with(
+                &format!("🔁 Replayed {} changes from the write-ahead log of '{}'", replayed, name),
+                &[("database", name)],
+            );
         }
 
-        Some(Self {
-            data: Arc::new(Mutex::new(data)),
+        let instance = Self {
+            data: data.into_iter().collect::<ShardedMap>().with_wal(name),
             require_auth: serialized.require_auth,
             username: serialized.username,
             password: serialized.password,
             name: name.to_string(),
-        })
+            dirty: AtomicBool::new(false),
+            persist_health: PersistHealth::default(),
+            quota: AtomicU64::new(serialized.quota.unwrap_or(0)),
+            default_ttl: AtomicU64::new(serialized.default_ttl.unwrap_or(0)),
+            exec_gate: RwLock::new(()),
+            dropped: AtomicBool::new(false),
+            file_lock: Mutex::new(()),
+        };
+
+        // Fold the replayed changes into the file at the next flush
+        if replayed > 0 {
+            instance.persist();
+        }
+        Some(instance)
     }
 
     /// Saves the database to file
     pub fn save_to_file(&self) -> std::io::Result<()> {
-        let path = format!("dbs/{}.json", self.name);
+        self.write_file(false)
+    }
+
+    /// Saves the database to file and waits until the OS reports the data
+    /// as durably on disk (fsync).
+    pub fn sync_to_file(&self) -> std::io::Result<()> {
+        self.write_file(true)
+    }
+
+    /// Serializes the database to its file, optionally fsyncing it. A
+    /// write-ahead log is cleared afterwards, since the file now holds its
+    /// changes; the file is then always fsynced first.
+    fn write_file(&self, sync: bool) -> std::io::Result<()> {
+        // A dropped database's file is gone and must stay gone. Checked under
+        // the lock, so a drop either waits for this save or is seen by it
+        let _file = self.file_lock.lock().unwrap();
+        if self.is_dropped() {
+            return Ok(());
+        }
+        let path = db_path(&self.name);
+        let wal_path = wal_path(&self.name);
+        let clear_wal = self.data.wal.is_some() || wal_path.exists();
+        let sync = sync || clear_wal;
         
-        let data = self.data.lock().unwrap();
+        // Held until the log is cleared, so no change can be logged in between
+        let data = self.data.read_all();
         
-        let mut serialized_data = HashMap::new();
-        for (key, val) in data.iter() {
-            let expires_at = val.expires_at.map(|instant| {
-                instant.checked_duration_since(Instant::now())
-                    .map(|dur| dur.as_secs())
-                    .unwrap_or(0)
-            });
-            
-            serialized_data.insert(
-                key.clone(),
-                SerializableValueWithExpiry {
-                    value: val.value.clone(),
-                    expires_at,
-                }
-            );
-        }
+        let serialized_data: HashMap<String, SerializableValueWithExpiry> = data
+            .iter()
+            .map(|(key, val)| (key.clone(), val.into()))
+            .collect();
         
         
         let serialized = SerializableDb {
@@ -129,66 +897,722 @@ impl DbInstance {
             require_auth: self.require_auth,
             username: self.username.clone(),
             password: self.password.clone(),
+            quota: self.quota(),
+            default_ttl: self.default_ttl().map(|ttl| ttl.as_secs()),
         };
         
         
         let json = match serde_json::to_string_pretty(&serialized) {
             Ok(j) => j,
             Err(e) => {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+                return Err(std::io::Error::other(e));
             }
         };
         
         
-        match File::create(&path) {
-            Ok(mut file) => {
-                if let Err(e) = file.write_all(json.as_bytes()) {
-                    return Err(e);
+        // Write a temporary file next to the target and rename it into place,
+        // so a crash mid-write never leaves a truncated database file behind
+        let tmp_path = path.with_extension("json.tmp");
+        let written = File::create(&tmp_path).and_then(|mut file| {
+            file.write_all(json.as_bytes())?;
+            if sync {
+                file.sync_all()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written.and_then(|()| fs::rename(&tmp_path, &path)) {
+            fs::remove_file(&tmp_path).unwrap_or(());
+            return Err(e);
+        }
+
+        // Make the rename itself durable
+        if sync {
+            File::open(data_dir())?.sync_all()?;
+        }
+
+        if clear_wal {
+            match &self.data.wal {
+                Some(wal) => wal.clear()?,
+                None => remove_wal_file(&wal_path)?,
+            }
+            File::open(data_dir())?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the keys of the database as single-line JSON for DUMP.
+    /// Credentials and settings such as the quota are left out.
+    pub fn dump(&self) -> String {
+        let data = self.data.read_all();
+        let dumped = DumpedDb {
+            data: data.iter().map(|(key, val)| (key.clone(), val.into())).collect(),
+        };
+        serde_json::to_string(&dumped).expect("dumped database serializes to JSON")
+    }
+
+    /// Replaces every key of the database with those in `payload`, as
+    /// produced by `dump`. Returns the number of keys restored or an error
+    /// message; the database is left unchanged on error.
+    pub fn restore(&self, payload: &str) -> Result<usize, String> {
+        let dumped: DumpedDb = serde_json::from_str(payload)
+            .map_err(|e| format!("Error: Invalid dump payload ({})", e))?;
+
+        let entries: Vec<(String, ValueWithExpiry)> = dumped
+            .data
+            .into_iter()
+            .map(|(key, val)| (key, val.into()))
+            .collect();
+        if let Some(limit) = self.quota() {
+            let size: u64 = entries
+                .iter()
+                .filter(|(_, v)| !v.is_expired())
+                .map(|(k, v)| (k.len() + v.value.size()) as u64)
+                .sum();
+            if size > limit {
+                return Err(format!("Error: Quota exceeded ({} of {} bytes)", size, limit));
+            }
+        }
+
+        let restored = entries.len();
+        {
+            // Keep transactions from seeing a half-restored database
+            let _guard = self.statement_guard();
+            let mut data = self.data.write_all();
+            let existing: Vec<String> = data.iter().map(|(key, _)| key.clone()).collect();
+            for key in existing {
+                data.remove(&key);
+            }
+            for (key, val) in entries {
+                data.insert(key, val);
+            }
+        }
+        self.persist();
+        Ok(restored)
+    }
+
+    /// Renders the live keys of the database as CSV rows for EXPORT, sorted
+    /// by key, after a `key,type,value,ttl` header. Lists, hashes and sets
+    /// are written as JSON and binary values as base64; the TTL is in seconds
+    /// and empty for keys without one.
+    pub fn to_csv(&self) -> Vec<String> {
+        let data = self.data.read_all();
+        let mut live: Vec<(&String, &ValueWithExpiry)> =
+            data.iter().filter(|(_, v)| !v.is_expired()).collect();
+        live.sort_by_key(|(key, _)| *key);
+
+        let mut rows = vec!["key,type,value,ttl".to_string()];
+        rows.extend(live.into_iter().map(|(key, val)| {
+            let value = match &val.value {
+                Value::String(s) => s.clone(),
+                Value::List(list) => serde_json::json!(list).to_string(),
+                // Sorted so exporting unchanged data gives the same file
+                Value::Hash(hash) => serde_json::json!(hash.iter().collect::<BTreeMap<_, _>>()).to_string(),
+                Value::Set(set) => {
+                    let mut members: Vec<&String> = set.iter().collect();
+                    members.sort();
+                    serde_json::json!(members).to_string()
                 }
-                Ok(())
+                Value::Bytes(bytes) => BASE64.encode(bytes),
+            };
+            let ttl = val.ttl().map(|remaining| remaining.as_secs().to_string()).unwrap_or_default();
+            format!(
+                "{},{},{},{}",
+                csv_field(key),
+                val.value.type_name(),
+                csv_field(&value),
+                ttl
+            )
+        }));
+        rows
+    }
+
+    /// Held while a single statement runs; many can run at once.
+    pub fn statement_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.exec_gate.read().unwrap()
+    }
+
+    /// Held while EXEC runs a transaction; waits for running statements to
+    /// finish and keeps new ones out until it is dropped.
+    pub fn transaction_guard(&self) -> RwLockWriteGuard<'_, ()> {
+        self.exec_gate.write().unwrap()
+    }
+
+    /// Number of keys in the database that haven't expired.
+    pub fn key_count(&self) -> usize {
+        self.data.read_all().values().filter(|v| !v.is_expired()).count()
+    }
+
+    /// Returns a live key picked uniformly at random, or `None` if the
+    /// database has none.
+    pub fn random_key(&self) -> Option<String> {
+        let data = self.data.read_all();
+        let live = data.values().filter(|v| !v.is_expired()).count();
+        if live == 0 {
+            return None;
+        }
+        data.iter()
+            .filter(|(_, v)| !v.is_expired())
+            .nth(random_below(live))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Returns about `count` live keys at scan position `cursor` or later,
+    /// and the cursor the next batch starts at, 0 once every shard is done.
+    /// Keys sharing a position are returned together, so a batch can hold a
+    /// few more. Shards are read-locked one at a time.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let shard_bits = SHARD_COUNT.trailing_zeros();
+        let first_shard = (cursor >> (u64::BITS - shard_bits)) as usize;
+
+        let mut keys = Vec::new();
+        for shard in first_shard..SHARD_COUNT {
+            let wanted = count.saturating_sub(keys.len());
+            if wanted == 0 {
+                // Continue at the start of this shard
+                return ((shard as u64) << (u64::BITS - shard_bits), keys);
             }
-            Err(e) => {
-                Err(e)
+
+            let data = self.data.shards[shard].read().unwrap();
+            let mut found: Vec<(u64, &String)> = data
+                .iter()
+                .filter(|(_, v)| !v.is_expired())
+                .map(|(key, _)| (scan_position(key), key))
+                .filter(|(position, _)| *position >= cursor)
+                .collect();
+            if found.len() > wanted {
+                // Split off the lowest positions, plus anything tied with the last
+                found.select_nth_unstable(wanted - 1);
+                let last = found[wanted - 1].0;
+                keys.extend(
+                    found
+                        .iter()
+                        .filter(|(position, _)| *position <= last)
+                        .map(|(_, key)| (*key).clone()),
+                );
+                return (last.checked_add(1).unwrap_or(0), keys);
             }
+            keys.extend(found.into_iter().map(|(_, key)| key.clone()));
+        }
+        (0, keys)
+    }
+
+    /// Removes all expired keys and returns their names.
+    /// Does not persist; callers decide when to save.
+    pub fn remove_expired(&self) -> Vec<String> {
+        let mut expired_keys = Vec::new();
+
+        // Sweep one shard at a time so commands on other shards keep running
+        for (shard, used) in self.data.shards.iter().zip(&self.data.used) {
+            let mut shard = shard.write().unwrap();
+            let mut freed = 0;
+            shard.retain(|k, v| {
+                let expired = v.is_expired();
+                if expired {
+                    expired_keys.push(k.clone());
+                    freed += entry_size(k, v);
+                }
+                !expired
+            });
+            used.fetch_sub(freed, Ordering::Relaxed);
+        }
+        expired_keys
+    }
+
+    /// Looks at up to `count` keys of shard `shard`, starting from a random
+    /// one, and removes those that expired. Returns how many of the keys
+    /// looked at have a TTL, and the names of the removed ones.
+    /// Does not persist; callers decide when to save.
+    pub fn remove_expired_sample(&self, shard: usize, count: usize) -> (usize, Vec<String>) {
+        let mut shard_data = self.data.shards[shard].write().unwrap();
+        if shard_data.is_empty() {
+            return (0, Vec::new());
+        }
+
+        let start = random_below(shard_data.len());
+        let mut with_ttl = 0;
+        let mut expired_keys = Vec::new();
+        for (key, val) in shard_data.iter().skip(start).chain(shard_data.iter().take(start)).take(count) {
+            if val.expires_at.is_some() {
+                with_ttl += 1;
+                if val.is_expired() {
+                    expired_keys.push(key.clone());
+                }
+            }
+        }
+
+        let mut freed = 0;
+        for key in &expired_keys {
+            if let Some(val) = shard_data.remove(key) {
+                freed += entry_size(key, &val);
+            }
+        }
+        self.data.used[shard].fetch_sub(freed, Ordering::Relaxed);
+        (with_ttl, expired_keys)
+    }
+
+    /// Returns the byte quota of the database, if one is set.
+    pub fn quota(&self) -> Option<u64> {
+        match self.quota.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Sets or clears (`None`) the byte quota of the database.
+    pub fn set_quota(&self, limit: Option<u64>) {
+        self.quota.store(limit.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Returns the TTL given to keys written without one, if set.
+    pub fn default_ttl(&self) -> Option<Duration> {
+        match self.default_ttl.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Sets or clears (`None`) the TTL given to keys written without one.
+    /// Keys already stored keep their expiry.
+    pub fn set_default_ttl(&self, ttl: Option<Duration>) {
+        self.default_ttl.store(ttl.map_or(0, |ttl| ttl.as_secs()), Ordering::Relaxed);
+    }
+
+    /// Approximate number of bytes used by the keys and values of the
+    /// database, counting expired keys until they are removed.
+    pub fn memory_used(&self) -> u64 {
+        self.data.memory_used()
+    }
+
+    /// Checks that storing a value of `value_size` bytes under `key` keeps
+    /// the database within the quota. `data` must hold the key's shard.
+    /// Usage comes from the running byte counts of the shards, so only the
+    /// key's shard needs to be locked; expired keys count until removed.
+    pub fn check_quota<G: Deref<Target = Shard>>(
+        &self,
+        data: &ShardGuard<'_, G>,
+        key: &str,
+        value_size: usize,
+    ) -> Result<(), String> {
+        let Some(limit) = self.quota() else {
+            return Ok(());
+        };
+
+        // The write replaces whatever is stored under the key
+        let replaced = data.shard(key).get(key).map_or(0, |val| entry_size(key, val));
+        let used = (data.memory_used() + (key.len() + value_size) as u64).saturating_sub(replaced);
+        if used > limit {
+            return Err(format!("Error: Quota exceeded ({} of {} bytes)", used, limit));
         }
+        Ok(())
     }
 
+    /// Checks that `key` and a value of `value_size` bytes fit in the memory
+    /// limit on their own. Room for anything smaller is made by eviction
+    /// once the value is stored.
+    pub fn check_memory(&self, key: &str, value_size: usize) -> Result<(), String> {
+        let Some(limit) = max_memory() else {
+            return Ok(());
+        };
+
+        let size = (key.len() + value_size) as u64;
+        if size > limit {
+            return Err(format!(
+                "Error: Value of {} bytes exceeds the memory limit of {} bytes",
+                size, limit
+            ));
+        }
+        Ok(())
+    }
+
+    /// Marks the database as changed. The file is rewritten by the next
+    /// `flush`, so a burst of writes costs a single save. Every write ends
+    /// here, so this is also where the key and memory limits are enforced;
+    /// callers must not hold any shard lock.
     pub fn persist(&self) {
-        if let Err(e) = self.save_to_file() {
-            log_info(&format!("⚠️ Failed to persist database '{}': {}", self.name, e));
+        self.evict_over_limit();
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Evicts keys until the database is within the key and memory limits:
+    /// expired keys first, then the least recently used ones.
+    fn evict_over_limit(&self) {
+        if !eviction_enabled() {
+            return;
+        }
+        let (max_keys, max_memory) = (max_keys(), max_memory());
+        let over = |count: usize, used: u64| {
+            max_keys.is_some_and(|limit| count > limit) || max_memory.is_some_and(|limit| used > limit)
+        };
+
+        let mut data = self.data.write_all();
+        let mut count = data.len();
+        let mut used = self.data.memory_used();
+        if !over(count, used) {
+            return;
+        }
+
+        // Expired keys sort first, then by last use
+        let mut candidates: Vec<(bool, u64, String)> = data
+            .iter()
+            .map(|(key, val)| (!val.is_expired(), val.last_access.get(), key.clone()))
+            .collect();
+        candidates.sort_unstable_by_key(|(live, last_access, _)| (*live, *last_access));
+
+        let mut evicted = 0;
+        for (_, _, key) in candidates {
+            if !over(count, used) {
+                break;
+            }
+            if let Some(val) = data.remove(&key) {
+                count -= 1;
+                used = used.saturating_sub(entry_size(&key, &val));
+                evicted += 1;
+            }
+        }
+        log_debug(&format!("🧹 Evicted {} keys from '{}' to stay within its limits", evicted, self.name));
+    }
+
+    /// Deletes the database file and write-ahead log and marks the database
+    /// as dropped. A save under way finishes first and later saves do
+    /// nothing, so the file is never written back. If the file can't be
+    /// deleted, the database is left as it was.
+    pub fn drop_files(&self) -> io::Result<()> {
+        let _file = self.file_lock.lock().unwrap();
+        fs::remove_file(db_path(&self.name))?;
+        self.dropped.store(true, Ordering::Release);
+        // The log only holds changes to the deleted file
+        if let Err(e) = remove_wal_file(&wal_path(&self.name)) {
+            log_warn(&format!("Error deleting write-ahead log of '{}': {}", self.name, e));
+        }
+        Ok(())
+    }
+
+    /// Returns true once the database has been dropped.
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    /// Saves the database if it changed since the last save, logging failures
+    /// instead of returning them. After repeated failures, saves are skipped
+    /// for a cooldown period so a single broken database doesn't spam the log.
+    pub fn flush(&self) {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let health = &self.persist_health;
+        if unix_millis() < health.retry_after.load(Ordering::Relaxed) {
+            self.dirty.store(true, Ordering::Release);
+            return;
+        }
+
+        match self.save_to_file() {
+            Ok(()) => {
+                let failures = health.consecutive_failures.swap(0, Ordering::Relaxed);
+                if failures >= PERSIST_FAILURE_THRESHOLD {
+                    log_info(&format!("✅ Persistence of database '{}' recovered", self.name));
+                }
+            }
+            Err(e) => {
+                // Keep the changes pending so a later flush retries them
+                self.dirty.store(true, Ordering::Release);
+                let failures = health.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= PERSIST_FAILURE_THRESHOLD {
+                    health.retry_after.store(
+                        unix_millis() + PERSIST_COOLDOWN.as_millis() as u64,
+                        Ordering::Relaxed,
+                    );
+                    log_error_with(
+                        &format!(
+                            "⚠️ Failed to persist database '{}' {} times in a row ({}), pausing saves for {}s",
+                            self.name,
+                            failures,
+                            e,
+                            PERSIST_COOLDOWN.as_secs()
+                        ),
+                        &[("database", &self.name)],
+                    );
+                } else {
+                    log_error_with(
+                        &format!("⚠️ Failed to persist database '{}': {}", self.name, e),
+                        &[("database", &self.name)],
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// The kinds of value a key can hold.
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(String),
+    List(VecDeque<String>),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    // Arbitrary bytes that aren't valid UTF-8, stored with `SET key <len>`
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Lowercase name of the value kind, as reported by TYPE.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::Bytes(_) => "bytes",
+        }
+    }
+
+    /// Stores raw bytes as a string if they are valid UTF-8, so string
+    /// commands keep working on text sent in binary form, and as bytes otherwise.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(s) => Value::String(s),
+            Err(e) => Value::Bytes(e.into_bytes()),
+        }
+    }
+
+    /// Returns the raw contents of string and binary values, or `None` for
+    /// other kinds.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(s) => Some(s.as_bytes()),
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the string contents, or `None` for other kinds.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Consumes the value, returning the string contents for string values.
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
         }
     }
+
+    /// Approximate number of bytes held by the value.
+    pub fn size(&self) -> usize {
+        match self {
+            Value::String(s) => s.len(),
+            Value::List(list) => list.iter().map(String::len).sum(),
+            Value::Hash(hash) => hash.iter().map(|(f, v)| f.len() + v.len()).sum(),
+            Value::Set(set) => set.iter().map(String::len).sum(),
+            Value::Bytes(bytes) => bytes.len(),
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
 }
 
 /// Represents a value in the database along with its optional expiration time.
 #[derive(Debug, Clone)]
 pub struct ValueWithExpiry {
     // The actual value stored in the DB.
-    pub value: String,
-    // When the key should expire (if any).
-    pub expires_at: Option<Instant>, 
+    pub value: Value,
+    // When the key should expire (if any), on the monotonic clock for cheap checks.
+    expires_at: Option<Instant>,
+    // The same deadline on the wall clock, which stays meaningful across restarts.
+    expires_at_wall: Option<SystemTime>,
+    // When the value was written. Overwriting a key (e.g. with SET) resets it,
+    // in-place edits such as TTL changes keep it.
+    pub created_at: SystemTime,
+    // Labels attached with SETMETA, empty for plain SET.
+    pub tags: Vec<String>,
+    // When the key was last read or written, for LRU eviction.
+    last_access: LastAccess,
 }
 
 impl ValueWithExpiry {
     /// Creates a new `ValueWithExpiry` with optional time-to-live.
-    pub fn new(value: String, ttl: Option<Duration>) -> Self {
+    pub fn new(value: impl Into<Value>, ttl: Option<Duration>) -> Self {
+        let mut entry = Self {
+            value: value.into(),
+            expires_at: None,
+            expires_at_wall: None,
+            created_at: SystemTime::now(),
+            tags: Vec::new(),
+            last_access: LastAccess::now(),
+        };
         // Calculate the expiry time if TTL is provided.
-        let expires_at = ttl.map(|d| Instant::now() + d);
+        entry.set_ttl(ttl);
 
-        // Log key insertion with TTL status.
-        let msg = match expires_at {
-            Some(time) => format!("New key inserted with TTL ({:?})", time),
-            None => "New key inserted with no TTL".to_string(),
-        };
-        log_info(&msg);
+        // Log key insertion with TTL status. This runs on every write, so it
+        // is only built when debug logging is on.
+        if log_enabled(LogLevel::Debug) {
+            let msg = match ttl {
+                Some(ttl) => format!("New key inserted with TTL ({:?})", ttl),
+                None => "New key inserted with no TTL".to_string(),
+            };
+            log_debug(&msg);
+        }
+
+        entry
+    }
+
+    /// Returns the monotonic deadline of the value, if it expires.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+
+    /// Returns the time left until the value expires, if it expires.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|time| time.saturating_duration_since(Instant::now()))
+    }
 
-        Self { value, expires_at }
+    /// Sets the value to expire after `ttl` from now, or never for `None`.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.expires_at = ttl.map(|d| Instant::now() + d);
+        self.expires_at_wall = ttl.map(|d| SystemTime::now() + d);
+    }
+
+    /// Sets the value to expire at an absolute wall-clock time, or never for `None`.
+    pub fn set_deadline(&mut self, deadline: Option<SystemTime>) {
+        self.expires_at = deadline.map(instant_for);
+        self.expires_at_wall = deadline;
     }
 
     /// Checks if the value has expired based on current time.
+    /// Always false while expiry is paused via `pause_expiry`.
     pub fn is_expired(&self) -> bool {
+        if expiry_paused() {
+            return false;
+        }
         self.expires_at
             .map(|time| Instant::now() > time)
             .unwrap_or(false)
     }
-}
\ No newline at end of file
+}
+/// Creates an empty database with a name no other test uses, in a data
+/// directory under the system temp directory shared by the test run.
+#[cfg(test)]
+pub fn test_instance() -> DbInstance {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    set_data_dir(std::env::temp_dir().join(format!("db-server-tests-{}", std::process::id())));
+    let name = format!("test_{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    DbInstance::new(name, false, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_racing_a_drop_never_bring_the_file_back() {
+        let db = Arc::new(test_instance());
+        let saver = {
+            let db = db.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    db.data.write(&["k"]).insert("k".to_string(), ValueWithExpiry::new(i.to_string(), None));
+                    db.sync_to_file().unwrap();
+                }
+            })
+        };
+        std::thread::sleep(Duration::from_millis(5));
+        db.drop_files().unwrap();
+        assert!(db.is_dropped());
+        saver.join().unwrap();
+        assert!(!db_path(&db.name).exists());
+        assert!(!db_path(&db.name).with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn saved_databases_reload_without_leaving_a_temp_file() {
+        let db = test_instance();
+        db.set_quota(Some(1000));
+        {
+            let mut data = db.data.write(&["k", "list"]);
+            data.insert("k".to_string(), ValueWithExpiry::new("v".to_string(), Some(Duration::from_secs(3600))));
+            data.insert("list".to_string(), ValueWithExpiry::new(Value::List(["a".to_string()].into()), None));
+        }
+        db.sync_to_file().unwrap();
+        // Saving over an existing file goes through the temp file as well
+        db.sync_to_file().unwrap();
+        assert!(db_path(&db.name).exists());
+        assert!(!db_path(&db.name).with_extension("json.tmp").exists());
+
+        let loaded = DbInstance::load_from_file(&db.name).expect("database reloads");
+        assert_eq!(loaded.quota(), Some(1000));
+        let data = loaded.data.read(&["k", "list"]);
+        let k = data.get("k").unwrap();
+        assert_eq!(k.value.as_str(), Some("v"));
+        assert!(k.ttl().is_some_and(|ttl| ttl > Duration::from_secs(3590)));
+        assert!(matches!(&data.get("list").unwrap().value, Value::List(list) if list == &["a"]));
+    }
+
+    #[test]
+    fn legacy_files_keep_their_ttls() {
+        // Files written before expiries were timestamps stored seconds remaining
+        let name = test_instance().name;
+        let legacy = r#"{
+            "data": {
+                "session": {"value": "abc", "expires_at": 120},
+                "forever": {"value": "x", "expires_at": null}
+            },
+            "require_auth": false,
+            "username": null,
+            "password": null
+        }"#;
+        fs::create_dir_all(data_dir()).unwrap();
+        fs::write(db_path(&name), legacy).unwrap();
+
+        let loaded = DbInstance::load_from_file(&name).expect("legacy file loads");
+        {
+            let data = loaded.data.read(&["session", "forever"]);
+            let ttl = data.get("session").unwrap().ttl().unwrap();
+            assert!(ttl > Duration::from_secs(115) && ttl <= Duration::from_secs(120));
+            assert_eq!(data.get("forever").unwrap().ttl(), None);
+        }
+
+        // Saving again stores the deadline as a timestamp
+        loaded.sync_to_file().unwrap();
+        let saved = fs::read_to_string(db_path(&name)).unwrap();
+        assert!(saved.contains("expires_at_unix") && !saved.contains("\"expires_at\""));
+        let reloaded = DbInstance::load_from_file(&name).unwrap();
+        let ttl = reloaded.data.read(&["session"]).get("session").unwrap().ttl().unwrap();
+        assert!(ttl > Duration::from_secs(115) && ttl <= Duration::from_secs(121));
+    }
+
+    #[test]
+    fn repeated_save_failures_pause_saving_for_a_while() {
+        let db = test_instance();
+        db.sync_to_file().unwrap();
+        // A directory where the temp file goes makes every save fail
+        let tmp_path = db_path(&db.name).with_extension("json.tmp");
+        fs::create_dir_all(&tmp_path).unwrap();
+        db.data.write(&["k"]).insert("k".to_string(), ValueWithExpiry::new("v".to_string(), None));
+        db.persist();
+        for _ in 0..PERSIST_FAILURE_THRESHOLD {
+            db.flush();
+        }
+        let health = &db.persist_health;
+        assert_eq!(health.consecutive_failures.load(Ordering::Relaxed), PERSIST_FAILURE_THRESHOLD);
+        assert!(health.retry_after.load(Ordering::Relaxed) > unix_millis());
+
+        // Saves are skipped during the cooldown, but the changes stay pending
+        fs::remove_dir(&tmp_path).unwrap();
+        db.flush();
+        assert!(!fs::read_to_string(db_path(&db.name)).unwrap().contains("\"k\""));
+
+        health.retry_after.store(0, Ordering::Relaxed);
+        db.flush();
+        assert!(fs::read_to_string(db_path(&db.name)).unwrap().contains("\"k\""));
+        assert_eq!(health.consecutive_failures.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,119 @@
+/// Signature and summary of every command, for `HELP`. A command with
+/// several forms has one entry per form. New commands must be added here
+/// as well as to `SESSION_COMMANDS` or `STATEMENTS`.
+const HELP: &[(&str, &str, &str)] = &[
+    // Session commands
+    ("create", "create <dbname> [ttl]", "Create a database, optionally with a default TTL"),
+    ("use", "use <dbname>", "Select a database, authenticating if required"),
+    ("SELECT", "SELECT <dbname>", "Switch to another database, saving the current one first"),
+    ("DESELECT", "DESELECT", "Save and release the selected database"),
+    ("AUTH", "AUTH <dbname> <username> <password>", "Authenticate against a database and select it"),
+    ("AUTH", "AUTH <username> <password>", "Authenticate against the selected database"),
+    ("MULTI", "MULTI", "Queue the following statements until EXEC or DISCARD"),
+    ("EXEC", "EXEC", "Run the queued statements without others interleaving"),
+    ("DISCARD", "DISCARD", "Drop the queued statements"),
+    ("drop", "drop <dbname>", "Delete a database"),
+    ("ADMIN", "ADMIN <password>", "Unlock admin commands for this session"),
+    ("PROTOCOL", "PROTOCOL lenprefix|line", "Frame value replies as $<len> or send them plain"),
+    ("PROTOCOL", "PROTOCOL crlf|lf", "End reply lines with \\r\\n or \\n"),
+    ("PROTOCOL", "PROTOCOL typed|plain", "Prefix replies with their type (+, -ERR, :, $) or not"),
+    ("COMMANDS", "COMMANDS", "List the commands available to this session"),
+    ("HELP", "HELP [command]", "Show the usage of every command, or of one"),
+    ("ECHO", "ECHO <text>", "Reply with the text"),
+    ("QUIT", "QUIT", "Save the selected database and disconnect"),
+    ("NEXTCLEAN", "NEXTCLEAN", "Seconds until the next expiry sweep"),
+    ("SUBSCRIBE", "SUBSCRIBE <channel>", "Stream messages published on a channel until UNSUBSCRIBE"),
+    ("PUBLISH", "PUBLISH <channel> <message>", "Send a message to a channel's subscribers"),
+    ("INFO", "INFO", "Server statistics as field:value lines"),
+    ("LISTDBS", "LISTDBS", "List every database, marking those that need authentication"),
+    ("DUMP", "DUMP", "Export the selected database's keys as JSON"),
+    ("RESTORE", "RESTORE <json>", "Replace the selected database's keys with a DUMP payload"),
+    ("EXPORT", "EXPORT csv", "Export the selected database's keys as CSV"),
+    ("METRICS", "METRICS", "Server counters as JSON"),
+    ("CLEANNOW", "CLEANNOW", "Remove expired keys from every loaded database now"),
+    ("PAUSEEXPIRY", "PAUSEEXPIRY <seconds>", "Suspend key expiry for a while"),
+    // Statements
+    ("SET", "SET(\"key\",\"value\",[\"ttl\"])", "Store a value, with a TTL such as 5s, 10m or 1d (\"\" for none)"),
+    ("SET", "SET <key> <len>", "Store the <len> raw bytes that follow the line"),
+    ("SETMETA", "SETMETA(\"key\",\"value\",\"ttl\",\"tag\"...)", "Store a value with a TTL (\"\" for none) and tags"),
+    ("SETNX", "SETNX(\"key\",\"value\",[\"ttl\"])", "Store a value only if the key doesn't exist"),
+    ("MSET", "MSET(\"k1\",\"v1\",\"k2\",\"v2\",...)", "Store several values at once"),
+    ("MSETNX", "MSETNX(\"k1\",\"v1\",\"k2\",\"v2\",...)", "Store several values only if none of the keys exist"),
+    ("GET", "GET(\"key\")", "Read a value"),
+    ("GET", "GET <key>", "Read a value as $<len> followed by its raw bytes"),
+    ("MGET", "MGET(\"k1\",\"k2\",...)", "Read several values, (nil) for missing keys"),
+    ("TOUCH", "TOUCH(\"k1\",\"k2\",...)", "Count the keys that exist"),
+    ("STRLEN", "STRLEN(\"key\")", "Byte length of a value"),
+    ("GETRANGE", "GETRANGE(\"key\",\"start\",\"end\")", "The bytes of a value between two inclusive offsets"),
+    ("GETRANGE", "GETRANGE <key> <start> <end>", "Read those bytes as $<len> followed by the raw bytes"),
+    ("TYPE", "TYPE(\"key\")", "Kind of value stored at a key, or none"),
+    ("TAGS", "TAGS(\"key\")", "List the tags of a key"),
+    ("KEYSBYTAG", "KEYSBYTAG(\"tag\")", "List the keys carrying a tag"),
+    ("TAGCOUNT", "TAGCOUNT(\"tag\")", "Count the keys carrying a tag"),
+    ("APPEND", "APPEND(\"key\",\"suffix\")", "Append to a value, returns the new length"),
+    ("SETRANGE", "SETRANGE(\"key\",\"offset\",\"value\")", "Overwrite part of a value, returns the new length"),
+    ("INCR", "INCR(\"key\")", "Add one to an integer value"),
+    ("DECR", "DECR(\"key\")", "Subtract one from an integer value"),
+    ("INCRBY", "INCRBY(\"key\",\"n\")", "Add n to an integer value"),
+    ("DECRBY", "DECRBY(\"key\",\"n\")", "Subtract n from an integer value"),
+    ("INCRBYFLOAT", "INCRBYFLOAT(\"key\",\"n\")", "Add a floating point n to a numeric value"),
+    ("GETSET", "GETSET(\"key\",\"value\")", "Store a value and return the previous one"),
+    ("GETDEL", "GETDEL(\"key\")", "Return a value and delete the key"),
+    ("GETORSET", "GETORSET(\"key\",\"default\",[\"ttl\"])", "Return a value, storing the default first if absent"),
+    ("GETRESET", "GETRESET(\"key\")", "Return an integer counter and reset it to 0"),
+    ("DEL", "DEL(\"key\")", "Delete a key"),
+    ("RENAME", "RENAME(\"old\",\"new\")", "Move a value and its TTL to a new key"),
+    ("COPY", "COPY(\"src\",\"dst\",[\"replace\"])", "Copy a value and its TTL to a new key"),
+    ("LPUSH", "LPUSH(\"key\",\"value\",...)", "Push onto the head of a list"),
+    ("RPUSH", "RPUSH(\"key\",\"value\",...)", "Push onto the tail of a list"),
+    ("LPOP", "LPOP(\"key\")", "Remove and return the head of a list"),
+    ("RPOP", "RPOP(\"key\")", "Remove and return the tail of a list"),
+    ("LLEN", "LLEN(\"key\")", "Length of a list"),
+    ("LRANGE", "LRANGE(\"key\",\"start\",\"stop\")", "List the elements between two inclusive indices"),
+    ("HSET", "HSET(\"key\",\"field\",\"value\")", "Set a hash field"),
+    ("HGET", "HGET(\"key\",\"field\")", "Read a hash field"),
+    ("HGETALL", "HGETALL(\"key\")", "List the fields and values of a hash"),
+    ("HDEL", "HDEL(\"key\",\"field\")", "Delete a hash field"),
+    ("SADD", "SADD(\"key\",\"member\",...)", "Add members to a set"),
+    ("SREM", "SREM(\"key\",\"member\",...)", "Remove members from a set"),
+    ("SMEMBERS", "SMEMBERS(\"key\")", "List the members of a set"),
+    ("SISMEMBER", "SISMEMBER(\"key\",\"member\")", "Whether a member is in a set"),
+    ("TTL", "TTL(\"key\")", "Remaining seconds, -1 without expiry, -2 if missing"),
+    ("KEYSTTL", "KEYSTTL(\"pattern\")", "List matching keys with their remaining seconds, -1 without expiry"),
+    ("EXPIRENX", "EXPIRENX(\"key\",\"ttl\")", "Set a TTL only if the key has none"),
+    ("EXPIREADD", "EXPIREADD(\"key\",\"ttl\")", "Extend the remaining TTL of a key"),
+    ("EXPIREALLAT", "EXPIREALLAT(\"pattern\",unix_seconds)", "Expire every matching key at a point in time"),
+    ("RECENTKEYS", "RECENTKEYS(seconds)", "List the keys written within the last N seconds"),
+    ("PURGE", "PURGE", "Remove expired keys from the database now"),
+    ("DBSIZE", "DBSIZE", "Number of live keys"),
+    ("RANDOMKEY", "RANDOMKEY", "A random live key"),
+    ("SCAN", "SCAN(cursor,[\"pattern\"],[count])", "The next cursor and a batch of keys"),
+    ("FSYNC", "FSYNC", "Write the database to disk and fsync it"),
+    ("SHARD", "SHARD(\"key\",n)", "Which of n shards a key maps to"),
+    ("QUOTA", "QUOTA", "Bytes used against the database quota"),
+    ("SETQUOTA", "SETQUOTA(bytes)", "Set the database byte quota, 0 removes it"),
+    ("DEFAULTTTL", "DEFAULTTTL", "TTL given to keys set without one"),
+    ("SETDEFAULTTTL", "SETDEFAULTTTL(\"ttl\")", "Set the default TTL, \"\" removes it"),
+    ("BIGKEYS", "BIGKEYS(n)", "The n keys with the largest values"),
+    ("MAPVALUES", "MAPVALUES(\"pattern\",\"upper|lower|trim\")", "Transform the values of matching keys"),
+];
+
+/// Returns a `<signature> - <summary>` line for every form of the commands
+/// in `available`.
+pub fn summary(available: &[String]) -> Vec<String> {
+    HELP.iter()
+        .filter(|(name, _, _)| available.iter().any(|a| a == name))
+        .map(|(_, signature, text)| format!("{} - {}", signature, text))
+        .collect()
+}
+
+/// Returns the lines of `summary` for the command `name`, ignoring case, or
+/// `None` if it isn't one of the commands in `available`.
+pub fn usage(name: &str, available: &[String]) -> Option<Vec<String>> {
+    let lines: Vec<String> = HELP
+        .iter()
+        .filter(|(command, _, _)| command.eq_ignore_ascii_case(name) && available.iter().any(|a| a == command))
+        .map(|(_, signature, text)| format!("{} - {}", signature, text))
+        .collect();
+    (!lines.is_empty()).then_some(lines)
+}
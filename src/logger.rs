@@ -1,23 +1,207 @@
 use chrono::Local;
-use std::fs::OpenOptions;
+use serde_json::{Map, Value as JsonValue};
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// File log entries are appended to unless configured otherwise
+pub const DEFAULT_LOG_FILE: &str = "output.log";
+
+/// Where log entries are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl From<&str> for LogTarget {
+    /// `-` means stdout; anything else is a file path.
+    fn from(value: &str) -> Self {
+        match value {
+            "-" => LogTarget::Stdout,
+            path => LogTarget::File(PathBuf::from(path)),
+        }
+    }
+}
+
+/// Where log entries are written, set once at startup.
+static TARGET: OnceLock<LogTarget> = OnceLock::new();
+
+/// The open log file, reused across entries. Holding the lock while writing
+/// keeps entries from concurrent tasks from interleaving.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Closes the log file so the next entry reopens it, e.g. after logrotate
+/// has moved it away.
+pub fn reopen() {
+    *LOG_FILE.lock().unwrap() = None;
+}
+
+/// Sets where log entries are written. Only the first call has an effect,
+/// so it must happen before anything is logged.
+pub fn set_target(target: LogTarget) {
+    let _ = TARGET.set(target);
+}
+
+/// Severity of a log entry, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Label written in front of each entry.
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    /// Parses a level name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How each log entry is formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // `[LEVEL timestamp] message`, for people reading the log
+    Text,
+    // One JSON object per line, for log aggregators
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    /// Parses a format name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether entries are written as JSON objects instead of text lines.
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Sets how log entries are formatted.
+pub fn set_format(format: LogFormat) {
+    JSON_FORMAT.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+/// Least severe level that is written; anything below it is dropped.
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Sets the least severe level that is written to the log.
+pub fn set_min_level(level: LogLevel) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns true if entries at `level` are written, so callers can skip
+/// building messages that would be dropped.
+pub fn log_enabled(level: LogLevel) -> bool {
+    level as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Logs a message to the configured target (`output.log` by default) if
+/// `level` passes the minimum level. Each log entry is timestamped with the
+/// local date and time. `context` fields are only written in JSON format,
+/// since text messages already mention them.
+fn log(level: LogLevel, message: &str, context: &[(&str, &str)]) {
+    if !log_enabled(level) {
+        return;
+    }
 
-/// Logs an info-level message to a file named `output.log`
-/// Each log entry is timestamped with the local date and time.
-pub fn log_info(message: &str) {
     // Get the current local timestamp
     let now = Local::now();
 
-    // Format the log message with timestamp and message content
-    let formatted = format!("[INFO {}] {}", now.format("%Y-%m-%d %H:%M:%S"), message);
+    // Format the log message with level, timestamp and message content
+    let formatted = if JSON_FORMAT.load(Ordering::Relaxed) {
+        let mut entry = Map::new();
+        entry.insert("level".to_string(), level.label().into());
+        entry.insert("timestamp".to_string(), now.to_rfc3339().into());
+        entry.insert("message".to_string(), message.into());
+        for (name, value) in context {
+            entry.insert(name.to_string(), (*value).into());
+        }
+        format!("{}\n", JsonValue::Object(entry))
+    } else {
+        format!("[{} {}] {}\n", level.label(), now.format("%Y-%m-%d %H:%M:%S"), message)
+    };
 
-    // Open or create the log file in append mode
-    let mut file = OpenOptions::new()
-        .create(true)   // Create the file if it doesn't exist
-        .append(true)   // Append to the file instead of overwriting it
-        .open("output.log")
-        .expect("Failed to open or create output.log");
+    let path = match TARGET.get() {
+        Some(LogTarget::Stdout) => {
+            print!("{}", formatted);
+            return;
+        }
+        Some(LogTarget::File(path)) => path.as_path(),
+        None => DEFAULT_LOG_FILE.as_ref(),
+    };
+
+    let mut file = LOG_FILE.lock().unwrap();
+    let file = file.get_or_insert_with(|| {
+        // Open or create the log file in append mode
+        OpenOptions::new()
+            .create(true)   // Create the file if it doesn't exist
+            .append(true)   // Append to the file instead of overwriting it
+            .open(path)
+            .expect("Failed to open or create the log file")
+    });
+
+    // Write the whole entry in one call so it lands as a single line
+    file.write_all(formatted.as_bytes()).expect("Failed to write to the log file");
+}
+
+/// Logs a debug-level message, for detail only useful while troubleshooting.
+pub fn log_debug(message: &str) {
+    log(LogLevel::Debug, message, &[]);
+}
+
+/// Logs an info-level message.
+pub fn log_info(message: &str) {
+    log(LogLevel::Info, message, &[]);
+}
+
+/// Logs a warning: something went wrong but the server carries on as normal.
+pub fn log_warn(message: &str) {
+    log(LogLevel::Warn, message, &[]);
+}
+
+/// Logs an error: an operation failed and its effect was lost.
+pub fn log_error(message: &str) {
+    log(LogLevel::Error, message, &[]);
+}
+
+/// Logs an info-level message with structured context fields, such as
+/// `("database", name)`, for JSON log consumers.
+pub fn log_info_with(message: &str, context: &[(&str, &str)]) {
+    log(LogLevel::Info, message, context);
+}
 
-    // Write the formatted message to the file, followed by a newline
-    writeln!(file, "{}", &formatted).expect("Failed to write to output.log");
+/// Logs an error with structured context fields.
+pub fn log_error_with(message: &str, context: &[(&str, &str)]) {
+    log(LogLevel::Error, message, context);
 }
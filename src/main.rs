@@ -3,208 +3,1540 @@
 // =======================================================
 mod cleaner;
 mod db;
+mod help;
 mod logger;
+mod metrics;
 mod parser;
+mod pubsub;
+mod resp;
+mod reply;
+use anyhow::Context;
 use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::cleaner::NextCleanAt;
 use crate::db::DbMap;
 use db::DbInstance;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
-use crate::logger::log_info;
+use tokio::sync::Semaphore;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::time::timeout;
+use crate::logger::{log_debug, log_error, log_info, log_info_with, log_warn, LogFormat, LogLevel, LogTarget};
+use crate::metrics::{Metrics, SharedMetrics};
+use crate::pubsub::{PubSub, SharedPubSub, EXPIRED_CHANNEL};
+use crate::reply::{Reply, ReplyWriter};
+
+/// Commands handled directly by the connection loop, paired with whether
+/// they are admin-only. New commands must be registered here to be listed
+/// by `COMMANDS`, and described in `help`.
+const SESSION_COMMANDS: &[(&str, bool)] = &[
+    ("create", false),
+    ("use", false),
+    ("SELECT", false),
+    ("DESELECT", false),
+    ("AUTH", false),
+    ("MULTI", false),
+    ("EXEC", false),
+    ("DISCARD", false),
+    ("drop", false),
+    ("ADMIN", false),
+    ("PROTOCOL", false),
+    ("COMMANDS", false),
+    ("HELP", false),
+    ("ECHO", false),
+    ("QUIT", false),
+    ("NEXTCLEAN", false),
+    ("SUBSCRIBE", false),
+    ("PUBLISH", false),
+    ("INFO", false),
+    ("LISTDBS", false),
+    ("DUMP", false),
+    ("RESTORE", false),
+    ("EXPORT", false),
+    ("METRICS", true),
+    ("CLEANNOW", true),
+    ("PAUSEEXPIRY", true),
+];
+
+/// Compares two secrets in time that depends only on their lengths, so a
+/// client can't learn how much of a guess was right from the reply time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for (i, byte) in a.iter().enumerate() {
+        diff |= usize::from(byte ^ b.get(i).copied().unwrap_or(0));
+    }
+    diff == 0
+}
+
+/// Names of the session commands and statements available to a session
+/// with the given privileges.
+fn available_commands(is_admin: bool, admin_enabled: bool) -> Vec<String> {
+    let mut names: Vec<String> = SESSION_COMMANDS
+        .iter()
+        // ADMIN is only offered when an admin password is configured
+        .filter(|(name, _)| *name != "ADMIN" || admin_enabled)
+        .filter(|(_, admin_only)| is_admin || !admin_only)
+        .map(|(name, _)| name.to_string())
+        .collect();
+    names.extend(parser::statement_names(is_admin));
+    names
+}
+
+/// Reply for a line that matched no command. Session commands with the
+/// wrong arguments end up here too, and are pointed at their usage.
+fn unknown_command(command: &str) -> String {
+    let name = parser::command_name(command);
+    if SESSION_COMMANDS.iter().any(|(session_command, _)| *session_command == name) {
+        return format!("Error: Wrong arguments for {}, see HELP {}", name, name);
+    }
+    parser::unknown_command(command)
+}
+
+/// Port TCP clients connect to unless configured otherwise
+const DEFAULT_PORT: &str = "4000";
+
+/// Interface the server listens on unless configured otherwise; all of them
+const DEFAULT_HOST: &str = "0.0.0.0";
+
+/// Clients served at once unless configured otherwise
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// How long a connection may go without sending a command unless configured otherwise
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Largest value accepted by `SET <key> <len>` (64 MiB). Clients announcing
+/// more are disconnected, since their payload can't be skipped safely.
+const MAX_BINARY_VALUE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Longest expiry pause PAUSEEXPIRY accepts (1 day)
+const MAX_EXPIRY_PAUSE_SECS: u64 = 24 * 60 * 60;
+
+/// Failed authentication attempts allowed before a client is disconnected
+const MAX_AUTH_ATTEMPTS: u8 = 3;
+
+/// Pause before answering a wrong admin password, to slow down guessing
+const FAILED_ADMIN_DELAY: Duration = Duration::from_secs(1);
+
+/// Range of bcrypt cost factors the bcrypt crate accepts
+const BCRYPT_COSTS: std::ops::RangeInclusive<u32> = 4..=31;
+
+/// Prefix of environment variables whose values are seeded as keys at startup
+const SEED_PREFIX: &str = "DBSEED_";
+
+/// Seeds keys from `DBSEED_<key>=<value>` environment variables into the
+/// database named by `SEED_DATABASE` (default `default`), creating it if
+/// needed. `SEED_ON_CONFLICT=overwrite` replaces existing keys, otherwise
+/// they are skipped.
+fn seed_from_env(all_dbs: &DbMap) {
+    let seeds: Vec<(String, String)> = env::vars()
+        .filter_map(|(name, value)| {
+            name.strip_prefix(SEED_PREFIX)
+                .map(|key| (key.to_string(), value))
+        })
+        .collect();
+    if seeds.is_empty() {
+        return;
+    }
+
+    let db_name = env::var("SEED_DATABASE").unwrap_or_else(|_| "default".to_string());
+    if let Err(e) = db::validate_db_name(&db_name) {
+        log_error(&format!("⚠️ Not seeding SEED_DATABASE: {}", e));
+        return;
+    }
+    let overwrite = env::var("SEED_ON_CONFLICT").is_ok_and(|v| v == "overwrite");
+    seed(all_dbs, &db_name, seeds, overwrite);
+}
+
+/// Stores `seeds` in the database `db_name`, loading or creating it first.
+/// Keys are written with SET, or SETNX unless `overwrite`, so seeding obeys
+/// the same limits, quota and default TTL as clients do.
+fn seed(all_dbs: &DbMap, db_name: &str, seeds: Vec<(String, String)>, overwrite: bool) {
+    let db_instance = all_dbs
+        .lock()
+        .unwrap()
+        .entry(db_name.to_string())
+        .or_insert_with(|| {
+            Arc::new(
+                db::DbInstance::load_from_file(db_name)
+                    .unwrap_or_else(|| db::DbInstance::new(db_name.to_string(), false, None, None)),
+            )
+        })
+        .clone();
+    let db_instance = Some(db_instance);
+
+    let command = if overwrite { "SET" } else { "SETNX" };
+    for (key, value) in seeds {
+        if key.is_empty() {
+            log_warn(&format!("⚠️ Ignoring seed variable '{}' with an empty key", SEED_PREFIX));
+            continue;
+        }
+        let statement = format!("{}({},{})", command, parser::quote(&key), parser::quote(&value));
+        match parser::parse_statement(&statement, &db_instance, false) {
+            Reply::Integer(0) => {
+                log_info(&format!("🌱 Seed key '{}' already exists in '{}', skipped", key, db_name))
+            }
+            Reply::Error(e) => log_warn(&format!("⚠️ Seed key '{}' not stored in '{}': {}", key, db_name, e)),
+            _ => log_info(&format!("🌱 Seeded key '{}' into '{}'", key, db_name)),
+        }
+    }
+}
+
+/// Settings a config file may contain, named like their environment variables
+const CONFIG_SETTINGS: [&str; 14] = [
+    "BIND_ADDRESS",
+    "CLEAN_INTERVAL",
+    "DATA_DIR",
+    "LOG_FILE",
+    "LOG_LEVEL",
+    "LOG_FORMAT",
+    "MAX_CONNECTIONS",
+    "IDLE_TIMEOUT",
+    "WAL",
+    "MAX_KEYS",
+    "MAX_MEMORY",
+    "UNIX_SOCKET",
+    "TCP",
+    "BCRYPT_COST",
+];
+
+/// Config file settings applied again on SIGHUP; the others need a restart
+#[cfg(unix)]
+const RELOADABLE_SETTINGS: [&str; 2] = ["CLEAN_INTERVAL", "LOG_LEVEL"];
+
+/// Parses the `NAME=value` lines of a config file. Blank lines and lines
+/// starting with `#` are skipped.
+fn parse_config_file(contents: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut settings = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            anyhow::bail!("Invalid config line {}, expected NAME=value", number + 1);
+        };
+        let name = name.trim();
+        if !CONFIG_SETTINGS.contains(&name) {
+            anyhow::bail!("Unknown setting '{}' on config line {}", name, number + 1);
+        }
+        settings.insert(name.to_string(), value.trim().to_string());
+    }
+    Ok(settings)
+}
+
+fn read_config_file(path: &std::path::Path) -> anyhow::Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Error reading config file '{}'", path.display()))?;
+    parse_config_file(&contents).with_context(|| format!("Invalid config file '{}'", path.display()))
+}
+
+fn parse_clean_interval(secs: &str) -> anyhow::Result<Duration> {
+    match secs.parse::<u64>() {
+        Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+        _ => anyhow::bail!(
+            "Invalid clean interval '{}', expected a positive number of seconds",
+            secs
+        ),
+    }
+}
+
+fn parse_log_level(level: &str) -> anyhow::Result<LogLevel> {
+    level.parse::<LogLevel>().map_err(|()| {
+        anyhow::anyhow!("Invalid log level '{}', expected debug, info, warn or error", level)
+    })
+}
+
+/// Startup options, read from the command line with environment variables
+/// and then the config file as fallbacks.
+struct Config {
+    // Host and port TCP clients connect to
+    address: String,
+    // Whether clients are accepted over TCP
+    tcp: bool,
+    // Unix domain socket to listen on as well (None = TCP only)
+    unix_socket: Option<PathBuf>,
+    // Time between two expiry sweeps
+    clean_interval: Duration,
+    // Directory database files are stored in
+    data_dir: PathBuf,
+    // Clients served at once; further connections are refused
+    max_connections: usize,
+    // How long a connection may go without sending a command (None = forever)
+    idle_timeout: Option<Duration>,
+    // Whether changes are appended to a write-ahead log
+    wal: bool,
+    // Keys each database may hold before the least recently used are evicted (None = unlimited)
+    max_keys: Option<usize>,
+    // Bytes each database may hold before the least recently used keys are evicted (None = unlimited)
+    max_memory: Option<u64>,
+    // Least severe log level that is written
+    log_level: LogLevel,
+    // Where log entries are written
+    log_target: LogTarget,
+    // How log entries are formatted
+    log_format: LogFormat,
+    // bcrypt cost for database passwords, checked by `bcrypt_cost` once
+    // logging is set up, since an invalid value only warns
+    bcrypt_cost: Option<String>,
+    // File the settings below the command line and environment come from
+    config_file: Option<PathBuf>,
+    // Settings read from the config file, to tell which ones a reload changes
+    file_settings: HashMap<String, String>,
+}
+
+impl Config {
+    /// Parses `[port] [--bind <host[:port]>] [--clean-interval <seconds>] [--data-dir <path>]
+    /// [--log-file <path>] [--max-connections <n>] [--idle-timeout <seconds>]
+    /// [--wal <on|off>] [--max-keys <n>] [--max-memory <bytes>]
+    /// [--unix-socket <path>] [--tcp <on|off>] [--bcrypt-cost <n>] [--config <path>]`.
+    /// The options can also be set with `BIND_ADDRESS`, `CLEAN_INTERVAL`, `DATA_DIR`,
+    /// `LOG_FILE`, `MAX_CONNECTIONS`, `IDLE_TIMEOUT`, `WAL`, `MAX_KEYS`,
+    /// `MAX_MEMORY`, `UNIX_SOCKET`, `TCP`, `BCRYPT_COST` and `CONFIG_FILE`,
+    /// or with `NAME=value` lines in the config file; flags take precedence
+    /// over the environment, which takes precedence over the file. Defaults are port 4000, host `0.0.0.0`, 5 seconds, `dbs`,
+    /// `output.log`, 1024 connections, 600 seconds, `off`, no key or memory
+    /// limit, no unix socket, `on` and bcrypt's default cost.
+    /// A log file of `-` means stdout, and an idle timeout, key limit or
+    /// memory limit of 0 disables it. A bind address with a port overrides
+    /// the port argument. TCP can only be turned off when a unix socket is set.
+    /// The log level and format are read from `LOG_LEVEL` (default `info`)
+    /// and `LOG_FORMAT` (`text` or `json`, default `text`).
+    fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        // The config file is found first, since its settings fill in for
+        // environment variables that aren't set
+        let config_file = match args.iter().skip(1).position(|arg| arg == "--config") {
+            Some(index) => Some(args.get(index + 2).context("--config needs a value")?.clone()),
+            None => env::var("CONFIG_FILE").ok(),
+        };
+        let config_file = config_file.filter(|path| !path.is_empty()).map(PathBuf::from);
+        let file_settings = match &config_file {
+            Some(path) => read_config_file(path)?,
+            None => HashMap::new(),
+        };
+        let setting = |name: &str| env::var(name).ok().or_else(|| file_settings.get(name).cloned());
+
+        let mut port = None;
+        let mut bind = setting("BIND_ADDRESS");
+        let mut clean_interval = setting("CLEAN_INTERVAL");
+        let mut data_dir = setting("DATA_DIR");
+        let mut log_file = setting("LOG_FILE");
+        let mut max_connections = setting("MAX_CONNECTIONS");
+        let mut idle_timeout = setting("IDLE_TIMEOUT");
+        let mut wal = setting("WAL");
+        let mut max_keys = setting("MAX_KEYS");
+        let mut max_memory = setting("MAX_MEMORY");
+        let mut unix_socket = setting("UNIX_SOCKET");
+        let mut tcp = setting("TCP");
+        let mut bcrypt_cost = setting("BCRYPT_COST");
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => {
+                    let value = args.next().context("--bind needs a value")?;
+                    bind = Some(value.clone());
+                }
+                "--clean-interval" => {
+                    let value = args.next().context("--clean-interval needs a value")?;
+                    clean_interval = Some(value.clone());
+                }
+                "--data-dir" => {
+                    let value = args.next().context("--data-dir needs a value")?;
+                    data_dir = Some(value.clone());
+                }
+                "--log-file" => {
+                    let value = args.next().context("--log-file needs a value")?;
+                    log_file = Some(value.clone());
+                }
+                "--max-connections" => {
+                    let value = args.next().context("--max-connections needs a value")?;
+                    max_connections = Some(value.clone());
+                }
+                "--idle-timeout" => {
+                    let value = args.next().context("--idle-timeout needs a value")?;
+                    idle_timeout = Some(value.clone());
+                }
+                "--wal" => {
+                    let value = args.next().context("--wal needs a value")?;
+                    wal = Some(value.clone());
+                }
+                "--max-keys" => {
+                    let value = args.next().context("--max-keys needs a value")?;
+                    max_keys = Some(value.clone());
+                }
+                "--max-memory" => {
+                    let value = args.next().context("--max-memory needs a value")?;
+                    max_memory = Some(value.clone());
+                }
+                "--unix-socket" => {
+                    let value = args.next().context("--unix-socket needs a value")?;
+                    unix_socket = Some(value.clone());
+                }
+                "--tcp" => {
+                    let value = args.next().context("--tcp needs a value")?;
+                    tcp = Some(value.clone());
+                }
+                "--bcrypt-cost" => {
+                    let value = args.next().context("--bcrypt-cost needs a value")?;
+                    bcrypt_cost = Some(value.clone());
+                }
+                // Already read above
+                "--config" => {
+                    args.next();
+                }
+                flag if flag.starts_with("--") => anyhow::bail!("Unknown option '{}'", flag),
+                _ if port.is_none() => port = Some(arg.clone()),
+                _ => anyhow::bail!("Unexpected argument '{}'", arg),
+            }
+        }
+
+        let port = port.unwrap_or_else(|| DEFAULT_PORT.to_string());
+        let address = match bind.as_deref() {
+            None | Some("") => format!("{}:{}", DEFAULT_HOST, port),
+            Some(bind) => bind_address(bind, &port),
+        };
+
+        let clean_interval = match clean_interval {
+            Some(secs) => parse_clean_interval(&secs)?,
+            None => cleaner::DEFAULT_CLEAN_INTERVAL,
+        };
+
+        let max_connections = match max_connections {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => anyhow::bail!(
+                    "Invalid connection limit '{}', expected a positive number",
+                    n
+                ),
+            },
+            None => DEFAULT_MAX_CONNECTIONS,
+        };
+
+        let idle_timeout = match idle_timeout {
+            Some(secs) => match secs.parse::<u64>() {
+                Ok(0) => None,
+                Ok(secs) => Some(Duration::from_secs(secs)),
+                Err(_) => anyhow::bail!(
+                    "Invalid idle timeout '{}', expected a number of seconds",
+                    secs
+                ),
+            },
+            None => Some(DEFAULT_IDLE_TIMEOUT),
+        };
+
+        let wal = match wal.as_deref() {
+            Some("on") => true,
+            Some("off") | None => false,
+            Some(other) => anyhow::bail!("Invalid WAL setting '{}', expected on or off", other),
+        };
+
+        let max_keys = match max_keys {
+            Some(n) => match n.parse::<usize>() {
+                Ok(0) => None,
+                Ok(n) => Some(n),
+                Err(_) => anyhow::bail!("Invalid key limit '{}', expected a number", n),
+            },
+            None => None,
+        };
+
+        let max_memory = match max_memory {
+            Some(bytes) => match bytes.parse::<u64>() {
+                Ok(0) => None,
+                Ok(bytes) => Some(bytes),
+                Err(_) => anyhow::bail!("Invalid memory limit '{}', expected a number of bytes", bytes),
+            },
+            None => None,
+        };
+
+        let unix_socket = unix_socket.filter(|path| !path.is_empty()).map(PathBuf::from);
+        if unix_socket.is_some() && !cfg!(unix) {
+            anyhow::bail!("Unix domain sockets are not supported on this platform");
+        }
+
+        let tcp = match tcp.as_deref() {
+            Some("on") | None => true,
+            Some("off") => false,
+            Some(other) => anyhow::bail!("Invalid TCP setting '{}', expected on or off", other),
+        };
+        if !tcp && unix_socket.is_none() {
+            anyhow::bail!("TCP can only be turned off when --unix-socket is set");
+        }
+
+        let log_level = match setting("LOG_LEVEL") {
+            Some(level) => parse_log_level(&level)?,
+            None => LogLevel::Info,
+        };
+
+        let log_format = match setting("LOG_FORMAT") {
+            Some(format) => match format.parse::<LogFormat>() {
+                Ok(format) => format,
+                Err(()) => anyhow::bail!("Invalid log format '{}', expected text or json", format),
+            },
+            None => LogFormat::Text,
+        };
+
+        Ok(Self {
+            address,
+            tcp,
+            unix_socket,
+            clean_interval,
+            data_dir: PathBuf::from(data_dir.unwrap_or_else(|| db::DEFAULT_DATA_DIR.to_string())),
+            max_connections,
+            idle_timeout,
+            wal,
+            max_keys,
+            max_memory,
+            log_level,
+            log_target: LogTarget::from(log_file.as_deref().unwrap_or(logger::DEFAULT_LOG_FILE)),
+            log_format,
+            bcrypt_cost,
+            config_file,
+            file_settings,
+        })
+    }
+}
+
+/// A client stream, accepted over TCP or a unix domain socket.
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Accepts the next TCP client, or never resolves when TCP is turned off.
+async fn accept_tcp(listener: Option<&TcpListener>) -> std::io::Result<(Box<dyn Connection>, String)> {
+    let Some(listener) = listener else {
+        return std::future::pending().await;
+    };
+    let (socket, peer) = listener.accept().await?;
+    Ok((Box::new(socket), peer.to_string()))
+}
+
+/// Accepts the next unix socket client, or never resolves when no socket is
+/// configured.
+#[cfg(unix)]
+async fn accept_unix(
+    listener: Option<&tokio::net::UnixListener>,
+) -> std::io::Result<(Box<dyn Connection>, String)> {
+    let Some(listener) = listener else {
+        return std::future::pending().await;
+    };
+    let (socket, _) = listener.accept().await?;
+    Ok((Box::new(socket), "unix socket".to_string()))
+}
+
+#[cfg(not(unix))]
+async fn accept_unix(_listener: Option<&()>) -> std::io::Result<(Box<dyn Connection>, String)> {
+    std::future::pending().await
+}
+
+/// Binds a unix domain socket at `path`, first removing a socket file left
+/// behind by a previous run. Any other kind of file there is left alone and
+/// the bind fails.
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Could not remove stale socket {}", path.display()))?;
+    }
+    tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Could not bind unix socket {}", path.display()))
+}
+
+/// Returns the bcrypt cost named by `value`, or `DEFAULT_COST` when it is
+/// unset. A value outside the range bcrypt accepts is logged and replaced
+/// by the default, rather than making every password hash fail later.
+fn bcrypt_cost(value: Option<&str>) -> u32 {
+    let Some(value) = value else {
+        return DEFAULT_COST;
+    };
+    match value.parse::<u32>() {
+        Ok(cost) if BCRYPT_COSTS.contains(&cost) => cost,
+        _ => {
+            log_warn(&format!(
+                "⚠️ Invalid bcrypt cost '{}', expected {} to {}; using {}",
+                value,
+                BCRYPT_COSTS.start(),
+                BCRYPT_COSTS.end(),
+                DEFAULT_COST
+            ));
+            DEFAULT_COST
+        }
+    }
+}
+
+/// Returns `bind` as a listening address, appending `port` when `bind` is
+/// only a host. IPv6 hosts may be given with or without brackets.
+fn bind_address(bind: &str, port: &str) -> String {
+    if bind.parse::<std::net::SocketAddr>().is_ok() {
+        return bind.to_string();
+    }
+    if bind.parse::<std::net::Ipv6Addr>().is_ok() {
+        return format!("[{}]:{}", bind, port);
+    }
+    if bind.starts_with('[') && bind.ends_with(']') {
+        return format!("{}:{}", bind, port);
+    }
+    match bind.rsplit_once(':') {
+        Some((_, bind_port)) if bind_port.parse::<u16>().is_ok() => bind.to_string(),
+        _ => format!("{}:{}", bind, port),
+    }
+}
+
+/// Resolves once the process receives SIGINT (Ctrl-C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log_error(&format!("Error listening for Ctrl-C: {}", e));
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                log_error(&format!("Error listening for SIGTERM: {}", e));
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Reopens the log file whenever the process receives SIGHUP, so rotation
+/// tools can move the file away without restarting the server, then reloads
+/// the config file if there is one.
+#[cfg(unix)]
+fn handle_hangup(
+    config_file: Option<PathBuf>,
+    mut file_settings: HashMap<String, String>,
+    interval_updates: watch::Sender<Duration>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+    tokio::spawn(async move {
+        match signal(SignalKind::hangup()) {
+            Ok(mut sighup) => {
+                while sighup.recv().await.is_some() {
+                    logger::reopen();
+                    log_info("📜 Log file reopened");
+                    let Some(path) = &config_file else {
+                        continue;
+                    };
+                    match reload_config(path, &file_settings, &interval_updates) {
+                        Ok(settings) => {
+                            file_settings = settings;
+                            log_info(&format!("🔄 Reloaded config file '{}'", path.display()));
+                        }
+                        Err(e) => log_error(&format!("Error reloading config, keeping the current settings: {:#}", e)),
+                    }
+                }
+            }
+            Err(e) => log_error(&format!("Error listening for SIGHUP: {}", e)),
+        }
+    });
+}
+
+/// Reads the config file at `path` again and applies its reloadable
+/// settings, which override the command line and environment from then on.
+/// Changes to the other settings are logged as ignored. Nothing is applied
+/// if the file is invalid. Returns the settings read.
+#[cfg(unix)]
+fn reload_config(
+    path: &std::path::Path,
+    previous: &HashMap<String, String>,
+    interval_updates: &watch::Sender<Duration>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let settings = read_config_file(path)?;
+    let interval = settings.get("CLEAN_INTERVAL").map(|secs| parse_clean_interval(secs)).transpose()?;
+    let level = settings.get("LOG_LEVEL").map(|level| parse_log_level(level)).transpose()?;
+
+    if let Some(interval) = interval
+        && *interval_updates.borrow() != interval
+    {
+        interval_updates.send_replace(interval);
+        log_info(&format!("🧹 Clean interval set to {} seconds", interval.as_secs()));
+    }
+    if let Some(level) = level {
+        logger::set_min_level(level);
+    }
+    for name in CONFIG_SETTINGS {
+        if !RELOADABLE_SETTINGS.contains(&name) && settings.get(name) != previous.get(name) {
+            log_warn(&format!("⚠️ {} changed in the config file but needs a restart, ignored", name));
+        }
+    }
+    Ok(settings)
+}
+
+/// Writes a `<channel> <message>` line for every message published on
+/// `channel` until the client sends `UNSUBSCRIBE`. Returns false if the
+/// connection should be closed instead.
+async fn stream_messages<R, W>(
+    reader: &mut R,
+    writer: &mut ReplyWriter<W>,
+    channel: &str,
+    mut messages: broadcast::Receiver<String>,
+) -> bool
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // read_until keeps partial input in the buffer if a message wins the race
+    let mut input = Vec::new();
+    loop {
+        let reply = tokio::select! {
+            message = messages.recv() => match message {
+                // Messages are data, whatever they look like
+                Ok(message) => Reply::bulk(format!("{} {}", channel, message)),
+                Err(RecvError::Lagged(missed)) => {
+                    Reply::error(format!("Error: {} messages were dropped", missed))
+                }
+                Err(RecvError::Closed) => return false,
+            },
+            read = reader.read_until(b'\n', &mut input) => {
+                match read {
+                    Ok(0) => return false, // Connection closed by client
+                    Ok(_) => {}
+                    Err(e) => {
+                        log_warn(&format!("Error reading from socket: {}", e));
+                        return false;
+                    }
+                }
+                let command = String::from_utf8_lossy(&input).trim().to_string();
+                input.clear();
+                match command.as_str() {
+                    "UNSUBSCRIBE" => {
+                        if let Err(e) = writer.write_reply(&Reply::ok()).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            return false;
+                        }
+                        return true;
+                    }
+                    "" => continue,
+                    _ => Reply::error("Error: Only UNSUBSCRIBE is allowed while subscribed"),
+                }
+            }
+        };
+        if let Err(e) = writer.write_reply(&reply).await {
+            log_warn(&format!("Error writing to socket: {}", e));
+            return false;
+        }
+    }
+}
+
+/// Writes `prompt` and reads the client's answer, trimmed. Returns None if
+/// the connection should be closed instead, which includes a client that
+/// stays silent for longer than the idle timeout.
+async fn ask<R, W>(
+    reader: &mut R,
+    writer: &mut ReplyWriter<W>,
+    prompt: &str,
+    idle_timeout: Option<Duration>,
+) -> Option<String>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if let Err(e) = writer.write_reply(&Reply::status(prompt)).await {
+        log_warn(&format!("Error writing to socket: {}", e));
+        return None;
+    }
+    let mut answer = String::new();
+    let read = match idle_timeout {
+        Some(limit) => timeout(limit, reader.read_line(&mut answer)).await,
+        None => Ok(reader.read_line(&mut answer).await),
+    };
+    match read {
+        Ok(Ok(0)) => None, // Connection closed by client
+        Ok(Ok(_)) => Some(answer.trim().to_string()),
+        Ok(Err(e)) => {
+            log_warn(&format!("Error reading from socket: {}", e));
+            None
+        }
+        Err(_) => {
+            log_debug("⏳ Closing connection that stopped answering a prompt");
+            if let Err(e) = writer.write_reply(&Reply::error("Error: Idle timeout, closing connection")).await {
+                log_warn(&format!("Error writing to socket: {}", e));
+            }
+            None
+        }
+    }
+}
+
+/// Saves a database if it changed, on the blocking thread pool so a slow
+/// disk doesn't stall other connections sharing the runtime.
+async fn flush_blocking(db_instance: Arc<DbInstance>) {
+    if let Err(e) = tokio::task::spawn_blocking(move || db_instance.flush()).await {
+        log_error(&format!("Error saving database: {}", e));
+    }
+}
+
+/// Returns the database named `db_name`, loading it from its file into
+/// `all_dbs` if it isn't in memory yet. Every caller gets the instance held
+/// in `all_dbs`, so sessions using the same database share its state.
+async fn find_db(all_dbs: &DbMap, db_name: &str) -> Option<Arc<DbInstance>> {
+    // Never look outside the data directory for a file
+    if db::validate_db_name(db_name).is_err() {
+        return None;
+    }
+    // Try to get from memory first
+    let in_memory = all_dbs.lock().unwrap().get(db_name).cloned();
+    match in_memory {
+        Some(db) => Some(db),
+        // If not in memory, try to load from file
+        None => load_blocking(db_name).await.map(|db| {
+            // Another connection may have loaded it meanwhile
+            let mut dbs = all_dbs.lock().unwrap();
+            dbs.entry(db_name.to_string()).or_insert_with(|| Arc::new(db)).clone()
+        }),
+    }
+}
+
+/// Loads a database from its file on the blocking thread pool.
+async fn load_blocking(db_name: &str) -> Option<DbInstance> {
+    let db_name = db_name.to_string();
+    tokio::task::spawn_blocking(move || DbInstance::load_from_file(&db_name))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Writes and fsyncs every loaded database. Unlike `persist`, this ignores
+/// the failure cooldown, since it is the last chance to save.
+fn save_all(all_dbs: &DbMap) {
+    let dbs = all_dbs.lock().unwrap();
+    for db_instance in dbs.values() {
+        match db_instance.sync_to_file() {
+            Ok(()) => log_info(&format!("💾 Saved database '{}'", db_instance.name)),
+            Err(e) => log_error(&format!(
+                "⚠️ Failed to save database '{}' on shutdown: {}",
+                db_instance.name, e
+            )),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Parse port from args or default to 4000
+    // Parse port and options from args, defaulting to port 4000
     let args: Vec<String> = env::args().collect();
-    let port = args
-        .get(1)
-        .map(|s| s.to_string())
-        .unwrap_or("4000".to_string());
-    let address = format!("0.0.0.0:{}", port);
+    let config = Config::from_args(&args)?;
+    let address = config.address;
+    db::set_data_dir(config.data_dir);
+    db::set_wal_enabled(config.wal);
+    db::set_max_keys(config.max_keys);
+    db::set_max_memory(config.max_memory);
+    logger::set_min_level(config.log_level);
+    logger::set_target(config.log_target);
+    logger::set_format(config.log_format);
+    // The cleaner interval can change when the config file is reloaded
+    let (interval_updates, clean_interval) = watch::channel(config.clean_interval);
+    #[cfg(unix)]
+    handle_hangup(config.config_file, config.file_settings, interval_updates);
+    // Without SIGHUP the config file is only read at startup
+    #[cfg(not(unix))]
+    let _ = (config.config_file, config.file_settings, interval_updates);
 
     // Shared state for all databases
     let all_dbs: DbMap = Arc::new(Mutex::new(HashMap::new()));
 
+    // Pub/sub channels shared by all connections
+    let pubsub: SharedPubSub = Arc::new(PubSub::default());
+
+    // Seed keys from the environment before accepting connections
+    seed_from_env(&all_dbs);
+
+    // Server-wide counters
+    let metrics: SharedMetrics = Arc::new(Metrics::default());
+    let started_at = Instant::now();
+
+    // Each connection holds a permit for as long as it is open
+    let connection_limit = Arc::new(Semaphore::new(config.max_connections));
+    let idle_timeout = config.idle_timeout;
+    let bcrypt_cost = bcrypt_cost(config.bcrypt_cost.as_deref());
+
+    // Admin commands are disabled unless an admin password is configured
+    let admin_password = env::var("ADMIN_PASSWORD").ok();
+
     // Start cleaner thread
-    cleaner::start_cleaner(all_dbs.clone()).await;
+    let next_clean_at: NextCleanAt = Arc::new(AtomicU64::new(0));
+    cleaner::start_cleaner(
+        all_dbs.clone(),
+        next_clean_at.clone(),
+        clean_interval.clone(),
+        pubsub.clone(),
+    )
+    .await;
+
+    // Write changed databases to disk in the background
+    cleaner::start_flusher(all_dbs.clone()).await;
 
-    // Create TCP listener
-    let listener = TcpListener::bind(&address).await?;
-    log_info(&format!("Server running on {}", address));
+    // Create the TCP listener and, if configured, the unix socket listener
+    let listener = match config.tcp {
+        true => {
+            let listener = TcpListener::bind(&address).await?;
+            log_info(&format!("Server running on {}", address));
+            Some(listener)
+        }
+        false => None,
+    };
+    #[cfg(unix)]
+    let unix_listener = match &config.unix_socket {
+        Some(path) => {
+            let listener = bind_unix_socket(path)?;
+            log_info(&format!("Server running on unix socket {}", path.display()));
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    let unix_listener: Option<()> = None;
 
     // =======================================================
     // 🧠 INFO: Main Connection Handling Loop
     // =======================================================
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
-        let (mut socket, _) = match listener.accept().await {
-            Ok(result) => result,
+        let accepted = tokio::select! {
+            accepted = accept_tcp(listener.as_ref()) => accepted,
+            accepted = accept_unix(unix_listener.as_ref()) => accepted,
+            _ = &mut shutdown => break,
+        };
+        let mut socket = match accepted {
+            Ok((socket, peer)) => {
+                log_debug(&format!("🔌 Accepted connection from {}", peer));
+                socket
+            }
             Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
+                log_error(&format!("Error accepting connection: {}", e));
+                continue;
+            }
+        };
+        // Refuse clients beyond the limit rather than queueing them
+        let permit = match connection_limit.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log_warn("⚠️ Connection limit reached, refusing a client");
+                tokio::spawn(async move {
+                    let _ = socket.write_all(b"Error: Too many connections\n").await;
+                });
                 continue;
             }
         };
         let all_dbs = all_dbs.clone();
+        let admin_password = admin_password.clone();
+        let next_clean_at = next_clean_at.clone();
+        let clean_interval = clean_interval.clone();
+        let metrics = metrics.clone();
+        let pubsub = pubsub.clone();
+        metrics.connections_total.fetch_add(1, Ordering::Relaxed);
+        metrics.connections_active.fetch_add(1, Ordering::Relaxed);
         // Spawn new task for each connection
         tokio::spawn(async move {
-            let (reader, mut writer) = socket.split();
+            // Released when the connection closes
+            let _permit = permit;
+            let (reader, writer) = tokio::io::split(socket);
             let mut reader = BufReader::new(reader);
+            // Redis clients open with a RESP array, which starts with `*`
+            if resp::starts_with_array(&mut reader, idle_timeout).await {
+                resp::serve(&mut reader, writer, &all_dbs, &metrics, idle_timeout).await;
+                metrics.connections_active.fetch_sub(1, Ordering::Relaxed);
+                return;
+            }
+            let mut writer = ReplyWriter::new(writer);
             let mut line = String::new();
+            // Statements from the current line that are still to be run
+            let mut pending: VecDeque<String> = VecDeque::new();
             let mut current_db_instance: Option<Arc<DbInstance>> = None;
-            loop {
-                line.clear();
-                let bytes_read = match reader.read_line(&mut line).await {
-                    Ok(0) => break, // Connection closed by client
-                    Ok(n) => n,
-                    Err(e) => {
-                        eprintln!("Error reading from socket: {}", e);
+            let mut is_admin = false;
+            // Failed AUTH attempts, reset by a successful one
+            let mut failed_auths: u8 = 0;
+            // Failed ADMIN attempts, reset by a successful one
+            let mut failed_admin_auths: u8 = 0;
+            // Statements queued since MULTI, or None outside a transaction
+            let mut transaction: Option<Vec<String>> = None;
+            // Set when a statement couldn't be queued, so EXEC discards the transaction
+            let mut transaction_failed = false;
+            'session: loop {
+                if pending.is_empty() {
+                    line.clear();
+                    let read = match idle_timeout {
+                        Some(limit) => timeout(limit, reader.read_line(&mut line)).await,
+                        None => Ok(reader.read_line(&mut line).await),
+                    };
+                    let bytes_read = match read {
+                        Ok(Ok(0)) => break, // Connection closed by client
+                        Ok(Ok(n)) => n,
+                        Ok(Err(e)) => {
+                            log_warn(&format!("Error reading from socket: {}", e));
+                            break;
+                        }
+                        // Save and close connections that went quiet
+                        Err(_) => {
+                            if let Some(db_instance) = &current_db_instance {
+                                flush_blocking(db_instance.clone()).await;
+                            }
+                            log_debug("⏳ Closing idle connection");
+                            if let Err(e) = writer.write_reply(&Reply::error("Error: Idle timeout, closing connection")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                            }
+                            break;
+                        }
+                    };
+
+                    if bytes_read == 0 {
                         break;
                     }
-                };
 
-                if bytes_read == 0 {
-                    break;
+                    // Strip an optional correlation ID, echoed back with every reply
+                    let (correlation_id, command) = reply::split_correlation_id(&line);
+                    writer.correlation_id = correlation_id;
+
+                    // A line may hold several statements separated by `;`
+                    pending.extend(parser::split_statements(command));
                 }
+                let Some(statement) = pending.pop_front() else {
+                    continue;
+                };
+                let command = statement.as_str();
 
-                let parts: Vec<&str> = line.trim().split_whitespace().collect();
+                let parts: Vec<&str> = command.split_whitespace().collect();
                 if parts.is_empty() {
                     continue;
                 }
+                metrics.commands_total.fetch_add(1, Ordering::Relaxed);
+
+                // A database dropped by another session is no longer selected
+                if let Some(dropped) = current_db_instance.take_if(|db| db.is_dropped()) {
+                    log_debug(&format!("Database '{}' was dropped, deselecting it", dropped.name));
+                }
+
+                // The raw bytes of `SET <key> <len>` follow its line. Read them
+                // first, so they are never mistaken for commands
+                let mut binary_value = None;
+                if parts[0] == "SET"
+                    && parts.len() == 3
+                    && let Ok(len) = parts[2].parse::<usize>()
+                {
+                    if len > MAX_BINARY_VALUE_BYTES {
+                        let response = Reply::error(format!("Error: Value too large (at most {} bytes)", MAX_BINARY_VALUE_BYTES));
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                        }
+                        break;
+                    }
+                    let mut bytes = vec![0; len];
+                    let read = match idle_timeout {
+                        Some(limit) => timeout(limit, reader.read_exact(&mut bytes)).await,
+                        None => Ok(reader.read_exact(&mut bytes).await),
+                    };
+                    match read {
+                        Ok(Ok(_)) => binary_value = Some(bytes),
+                        Ok(Err(e)) => {
+                            log_warn(&format!("Error reading from socket: {}", e));
+                            break;
+                        }
+                        Err(_) => {
+                            log_debug("⏳ Closing connection that stopped sending a value");
+                            break;
+                        }
+                    }
+                }
+
+                // Inside MULTI, statements are queued until EXEC instead of running
+                if let Some(queued) = &mut transaction
+                    && !matches!(parts[0], "MULTI" | "EXEC" | "DISCARD" | "QUIT")
+                {
+                    let response = if binary_value.is_some() {
+                        transaction_failed = true;
+                        Reply::error("Error: SET <key> <len> can't be used inside MULTI")
+                    } else if !parser::is_statement(command) {
+                        transaction_failed = true;
+                        if SESSION_COMMANDS.iter().any(|(name, _)| *name == parts[0]) {
+                            Reply::error(format!("Error: {} can't be used inside MULTI", parts[0]))
+                        } else {
+                            Reply::error(unknown_command(command))
+                        }
+                    } else if !is_admin && parser::is_admin_command(command) {
+                        transaction_failed = true;
+                        Reply::error("Error: Admin privileges required")
+                    } else {
+                        queued.push(command.to_string());
+                        Reply::status("QUEUED")
+                    };
+                    if let Err(e) = writer.write_reply(&response).await {
+                        log_warn(&format!("Error writing to socket: {}", e));
+                        break;
+                    }
+                    continue;
+                }
 
                 match parts[0] {
-                    // Create a new database
-                    "create" if parts.len() == 2 => {
+                    // Elevate the session to admin
+                    "ADMIN" if parts.len() == 2 => {
+                        let response = match &admin_password {
+                            Some(password) if constant_time_eq(password.as_bytes(), parts[1].as_bytes()) => {
+                                is_admin = true;
+                                failed_admin_auths = 0;
+                                log_info("🔑 Session authenticated as admin");
+                                Reply::ok()
+                            }
+                            Some(_) => {
+                                failed_admin_auths += 1;
+                                log_warn(&format!(
+                                    "🚫 Invalid admin password ({} of {} attempts)",
+                                    failed_admin_auths, MAX_AUTH_ATTEMPTS
+                                ));
+                                tokio::time::sleep(FAILED_ADMIN_DELAY).await;
+                                if failed_admin_auths >= MAX_AUTH_ATTEMPTS {
+                                    if let Err(e) = writer.write_reply(&Reply::error("Too many failed authentication attempts. Disconnecting.")).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
+                                    }
+                                    break;
+                                }
+                                Reply::error("Error: Invalid admin password")
+                            }
+                            None => Reply::error("Error: Admin commands are disabled"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Negotiate the reply format for values and line endings
+                    "PROTOCOL" if parts.len() == 2 => {
+                        let response = match parts[1] {
+                            "lenprefix" => {
+                                writer.length_prefixed = true;
+                                Reply::ok()
+                            }
+                            "line" => {
+                                writer.length_prefixed = false;
+                                Reply::ok()
+                            }
+                            "crlf" => {
+                                writer.crlf = true;
+                                Reply::ok()
+                            }
+                            "lf" => {
+                                writer.crlf = false;
+                                Reply::ok()
+                            }
+                            "typed" => {
+                                writer.typed = true;
+                                Reply::ok()
+                            }
+                            "plain" => {
+                                writer.typed = false;
+                                Reply::ok()
+                            }
+                            _ => Reply::error("Error: Unknown protocol (use lenprefix, line, crlf, lf, typed or plain)"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // List the commands available to this session
+                    "COMMANDS" if parts.len() == 1 => {
+                        let names = available_commands(is_admin, admin_password.is_some());
+                        if let Err(e) = writer.write_reply(&Reply::statuses(names)).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Describe the commands available to this session, or one of them
+                    "HELP" if parts.len() <= 2 => {
+                        let names = available_commands(is_admin, admin_password.is_some());
+                        let response = match parts.get(1) {
+                            None => Reply::statuses(help::summary(&names)),
+                            Some(name) => match help::usage(name, &names) {
+                                Some(lines) => Reply::statuses(lines),
+                                None if help::usage(name, &available_commands(true, admin_password.is_some())).is_some() => {
+                                    Reply::error("Error: Admin privileges required")
+                                }
+                                None => Reply::error(format!("Error: Unknown command '{}', see HELP", name)),
+                            },
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Reply with the given text, unquoted if it is quoted
+                    "ECHO" if parts.len() >= 2 => {
+                        let text = parser::unquote(command.strip_prefix("ECHO").unwrap_or(""));
+                        if let Err(e) = writer.write_reply(&Reply::bulk(text)).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Save the selected database and close the connection
+                    "QUIT" if parts.len() == 1 => {
+                        if let Some(db_instance) = &current_db_instance {
+                            flush_blocking(db_instance.clone()).await;
+                        }
+                        if let Err(e) = writer.write_reply(&Reply::status("BYE")).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                        }
+                        break;
+                    }
+                    // Stream messages published on a channel until UNSUBSCRIBE
+                    "SUBSCRIBE" if parts.len() == 2 => {
+                        let channel = parts[1];
+                        let messages = pubsub.subscribe(channel);
+                        if let Err(e) = writer.write_reply(&Reply::status(format!("Subscribed to {}", channel))).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                        if !stream_messages(&mut reader, &mut writer, channel, messages).await {
+                            break;
+                        }
+                    }
+                    // Send a message to every subscriber of a channel
+                    "PUBLISH" if parts.len() >= 3 => {
+                        let channel = parts[1];
+                        let response = if channel == EXPIRED_CHANNEL {
+                            Reply::error(format!("Error: Channel '{}' is reserved for expiry events", channel))
+                        } else {
+                            // The message is the rest of the line, unquoted if it is quoted
+                            let rest = command.strip_prefix("PUBLISH").unwrap_or("").trim_start();
+                            let message = parser::unquote(rest.strip_prefix(channel).unwrap_or(""));
+                            Reply::Integer(pubsub.publish(channel, message) as i64)
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Report seconds until the cleaner's next sweep
+                    "NEXTCLEAN" if parts.len() == 1 => {
+                        let seconds = cleaner::seconds_until_next_clean(&next_clean_at);
+                        if let Err(e) = writer.write_reply(&Reply::Integer(seconds as i64)).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Report server statistics as field:value lines
+                    "INFO" if parts.len() == 1 => {
+                        let dbs: Vec<Arc<DbInstance>> = all_dbs.lock().unwrap().values().cloned().collect();
+                        let keys: usize = dbs.iter().map(|db| db.key_count()).sum();
+                        let response = Reply::statuses([
+                            format!("uptime_seconds:{}", started_at.elapsed().as_secs()),
+                            format!("databases_loaded:{}", dbs.len()),
+                            format!("keys:{}", keys),
+                            format!("clean_interval_seconds:{}", clean_interval.borrow().as_secs()),
+                            format!("commands_total:{}", metrics.commands_total.load(Ordering::Relaxed)),
+                        ]);
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // List databases in memory and on disk, flagging those that need auth
+                    "LISTDBS" if parts.len() == 1 => {
+                        let mut databases: BTreeMap<String, bool> = all_dbs
+                            .lock()
+                            .unwrap()
+                            .iter()
+                            .map(|(name, db)| (name.clone(), db.require_auth))
+                            .collect();
+                        let stored = tokio::task::spawn_blocking(db::stored_databases)
+                            .await
+                            .unwrap_or_default();
+                        for (name, require_auth) in stored {
+                            databases.entry(name).or_insert(require_auth);
+                        }
+
+                        let names: Vec<String> = databases
+                            .into_iter()
+                            .map(|(name, require_auth)| {
+                                if require_auth { format!("{} (auth)", name) } else { name }
+                            })
+                            .collect();
+                        if let Err(e) = writer.write_reply(&Reply::statuses(names)).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Store the raw bytes that followed `SET <key> <len>`
+                    "SET" if binary_value.is_some() => {
+                        let bytes = binary_value.take().unwrap_or_default();
+                        let response = parser::set_bytes(parts[1], bytes, &current_db_instance);
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Reply with a value as `$<len>\r\n<bytes>`, safe for binary data
+                    "GET" if parts.len() == 2 => {
+                        let written = match parser::get_bytes(parts[1], &current_db_instance) {
+                            Ok(value) => writer.write_bulk(value.as_deref()).await,
+                            Err(e) => writer.write_reply(&e).await,
+                        };
+                        if let Err(e) = written {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Reply with part of a value as `$<len>\r\n<bytes>`, byte for byte
+                    "GETRANGE" if parts.len() == 4 => {
+                        let written = match parser::get_range_bytes(parts[1], parts[2], parts[3], &current_db_instance) {
+                            Ok(value) => writer.write_bulk(Some(&value)).await,
+                            Err(e) => writer.write_reply(&e).await,
+                        };
+                        if let Err(e) = written {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Export the selected database's keys as one line of JSON
+                    "DUMP" if parts.len() == 1 => {
+                        let response = match &current_db_instance {
+                            Some(db_instance) => Reply::bulk(db_instance.dump()),
+                            None => Reply::error("No database selected"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Replace the selected database's keys with a DUMP payload
+                    "RESTORE" if parts.len() >= 2 => {
+                        let response = match &current_db_instance {
+                            Some(db_instance) => {
+                                let payload = command.strip_prefix("RESTORE").unwrap_or("").trim();
+                                match db_instance.restore(payload) {
+                                    Ok(restored) => {
+                                        log_info_with(
+                                            &format!("📥 Restored {} keys into '{}'", restored, db_instance.name),
+                                            &[("database", &db_instance.name), ("command", "RESTORE")],
+                                        );
+                                        Reply::Integer(restored as i64)
+                                    }
+                                    Err(e) => Reply::error(e),
+                                }
+                            }
+                            None => Reply::error("No database selected"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Export the selected database's keys in another format
+                    "EXPORT" if parts.len() == 2 => {
+                        let response = match (&current_db_instance, parts[1]) {
+                            (Some(db_instance), "csv") => {
+                                Reply::bulks(db_instance.to_csv())
+                            }
+                            (Some(_), _) => Reply::error("Error: Unknown export format (use csv)"),
+                            (None, _) => Reply::error("No database selected"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Report server counters as JSON
+                    "METRICS" if parts.len() == 1 => {
+                        let response = if is_admin {
+                            let databases_loaded = all_dbs.lock().unwrap().len();
+                            Reply::bulk(metrics.to_json(databases_loaded))
+                        } else {
+                            Reply::error("Error: Admin privileges required")
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Run an expiry sweep across all databases immediately
+                    "CLEANNOW" if parts.len() == 1 => {
+                        let response = if is_admin {
+                            let removed = cleaner::sweep_expired(&all_dbs, &pubsub);
+                            log_info(&format!("🧼 CLEANNOW removed {} expired keys", removed));
+                            Reply::Integer(removed as i64)
+                        } else {
+                            Reply::error("Error: Admin privileges required")
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Suspend key expiry server-wide for a number of seconds
+                    "PAUSEEXPIRY" if parts.len() == 2 => {
+                        let response = if !is_admin {
+                            Reply::error("Error: Admin privileges required")
+                        } else {
+                            match parts[1].parse::<u64>() {
+                                Ok(secs) if secs > MAX_EXPIRY_PAUSE_SECS => Reply::error(format!(
+                                    "Error: Pause too long (at most {} seconds)",
+                                    MAX_EXPIRY_PAUSE_SECS
+                                )),
+                                Ok(secs) => {
+                                    db::pause_expiry(Duration::from_secs(secs));
+                                    log_info(&format!("⏸️ Expiry paused for {} seconds", secs));
+                                    Reply::ok()
+                                }
+                                Err(_) => Reply::error("Error: Invalid number of seconds"),
+                            }
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Create a new database, optionally with a default TTL
+                    "create" if parts.len() == 2 || parts.len() == 3 => {
                         // Check if a database is already selected
                         if current_db_instance.is_some() {
-                            if let Err(e) = writer
-                                .write_all(
-                                    b"Cannot create a database. A database is already selected.\n",
-                                )
-                                .await
-                            {
-                                eprintln!("Error writing to socket: {}", e);
+                            if let Err(e) = writer.write_reply(&Reply::error("Cannot create a database. A database is already selected.")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
                                 break;
                             }
                         } else {
                             let db_name = parts[1].to_string();
-                            let path = format!("dbs/{}.json", db_name);
-                            if Path::new(&path).exists() {
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Error: Database '{}' already exists\n", db_name)
-                                            .as_bytes(),
-                                    )
-                                    .await
-                                {
-                                    eprintln!("Error writing to socket: {}", e);
+                            if let Err(e) = db::validate_db_name(&db_name) {
+                                if let Err(e) = writer.write_reply(&Reply::error(e)).await {
+                                    log_warn(&format!("Error writing to socket: {}", e));
                                     break;
                                 }
                                 continue;
                             }
-                            // Ask for authentication preference
-                            if let Err(e) = writer
-                                .write_all(b"Do you want authentication (yes/no)?\n")
-                                .await
-                            {
-                                eprintln!("Error writing to socket: {}", e);
-                                break;
-                            }
-                            // Read authentication preference
-                            let mut auth_line = String::new();
-                            if let Err(e) = reader.read_line(&mut auth_line).await {
-                                eprintln!("Error reading auth option: {}", e);
-                                break;
-                            }
-                            let auth_option = auth_line.trim().to_lowercase() == "yes";
-                            // If authentication is required, ask for username and password
-                            let db_instance = if auth_option {
-                                if let Err(e) = writer.write_all(b"Enter username:\n").await {
-                                    eprintln!("Error writing to socket: {}", e);
+                            if db::db_path(&db_name).exists() {
+                                if let Err(e) = writer.write_reply(&Reply::error(format!("Error: Database '{}' already exists", db_name))).await {
+                                    log_warn(&format!("Error writing to socket: {}", e));
                                     break;
                                 }
-
-                                let mut username_line = String::new();
-                                if let Err(e) = reader.read_line(&mut username_line).await {
-                                    eprintln!("Error reading username: {}", e);
-                                    break;
+                                continue;
+                            }
+                            let default_ttl = match parts.get(2).map(|ttl| parser::parse_duration(ttl)) {
+                                Some(Ok(ttl)) if ttl.is_zero() => {
+                                    if let Err(e) = writer.write_reply(&Reply::error("Error: Default TTL must be positive")).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
+                                        break;
+                                    }
+                                    continue;
                                 }
-                                let username = username_line.trim().to_string();
-
-                                if let Err(e) = writer.write_all(b"Enter password:\n").await {
-                                    eprintln!("Error writing to socket: {}", e);
-                                    break;
+                                Some(Ok(ttl)) => Some(ttl),
+                                Some(Err(e)) => {
+                                    if let Err(e) = writer.write_reply(&Reply::error(e)).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
+                                        break;
+                                    }
+                                    continue;
                                 }
-
-                                let mut password_line = String::new();
-                                if let Err(e) = reader.read_line(&mut password_line).await {
-                                    eprintln!("Error reading password: {}", e);
+                                None => None,
+                            };
+                            // Ask for authentication preference
+                            let Some(auth_answer) = ask(&mut reader, &mut writer, "Do you want authentication (yes/no)?", idle_timeout).await else {
+                                break;
+                            };
+                            let auth_option = auth_answer.to_lowercase() == "yes";
+                            // If authentication is required, ask for username and password
+                            let credentials = if auth_option {
+                                let Some(username) = ask(&mut reader, &mut writer, "Enter username:", idle_timeout).await else {
                                     break;
-                                }
-                                let password = password_line.trim().to_string();
-                                let hashed_password = match hash(&password, DEFAULT_COST) {
+                                };
+                                let Some(password) = ask(&mut reader, &mut writer, "Enter password:", idle_timeout).await else {
+                                    break;
+                                };
+                                let hashed_password = match hash(&password, bcrypt_cost) {
                                     Ok(hashed) => hashed,
                                     Err(e) => {
-                                        eprintln!("Error hashing password: {}", e);
-                                        if let Err(e) = writer.write_all(b"Error creating database\n").await {
-                                            eprintln!("Error writing to socket: {}", e);
+                                        log_error(&format!("Error hashing password: {}", e));
+                                        if let Err(e) = writer.write_reply(&Reply::error("Error creating database")).await {
+                                            log_warn(&format!("Error writing to socket: {}", e));
                                         }
                                         break;
                                     }
                                 };
-                                db::DbInstance::new(
-                                    db_name.clone(),
-                                    true,
-                                    Some(username),
-                                    Some(hashed_password),
-                                )
+                                Some((username, hashed_password))
                             } else {
-                                db::DbInstance::new(db_name.clone(), false, None, None)
+                                None
+                            };
+
+                            // Creating a database writes its file, so keep it off the runtime
+                            let name = db_name.clone();
+                            let created = tokio::task::spawn_blocking(move || {
+                                let db_instance = match credentials {
+                                    Some((username, password)) => {
+                                        DbInstance::new(name, true, Some(username), Some(password))
+                                    }
+                                    None => DbInstance::new(name, false, None, None),
+                                };
+                                // Saved along with the first flush
+                                if default_ttl.is_some() {
+                                    db_instance.set_default_ttl(default_ttl);
+                                    db_instance.persist();
+                                }
+                                db_instance
+                            })
+                            .await;
+                            let db_instance = match created {
+                                Ok(db_instance) => db_instance,
+                                Err(e) => {
+                                    log_error(&format!("Error creating database: {}", e));
+                                    if let Err(e) = writer.write_reply(&Reply::error("Error creating database")).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
+                                    }
+                                    break;
+                                }
                             };
 
                             // Insert new database into shared state
                             {
                                 let mut dbs = all_dbs.lock().unwrap();
-                                dbs.insert(db_name, db_instance);
+                                dbs.insert(db_name, Arc::new(db_instance));
                             }
 
                             // Confirm database creation
-                            if let Err(e) =
-                                writer.write_all(b"Database created successfully\n").await
-                            {
-                                eprintln!("Error writing to socket: {}", e);
+                            if let Err(e) = writer.write_reply(&Reply::status("Database created successfully")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
                                 break;
                             }
                         }
                     }
-                    // Use a database
-                    "use" if parts.len() == 2 => {
-                        // Check if a database is already selected
-                        if current_db_instance.is_some() {
-                            if let Err(e) = writer.write_all(b"Cannot use a different database. A database is already selected.\n").await {
-                                eprintln!("Error writing to socket: {}", e);
+                    // Use a database, or switch to another one with SELECT
+                    "use" | "SELECT" if parts.len() == 2 => {
+                        // Only SELECT may replace an existing selection
+                        if parts[0] == "use" && current_db_instance.is_some() {
+                            if let Err(e) = writer.write_reply(&Reply::error("Cannot use a different database. A database is already selected, use SELECT to switch.")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
+                            }
+                        } else if let Err(e) = db::validate_db_name(parts[1]) {
+                            if let Err(e) = writer.write_reply(&Reply::error(e)).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
                                 break;
                             }
                         } else {
-                            let db_name = parts[1];
-                            let db_instance = {
-                                let mut dbs = all_dbs.lock().unwrap();
+                            // Flush the database being switched away from; it stays
+                            // selected until the new one is in use
+                            if let Some(previous) = &current_db_instance {
+                                flush_blocking(previous.clone()).await;
+                            }
 
-                                // Try to get from memory first
-                                if let Some(db) = dbs.get(db_name) {
-                                    Some(db.clone())
-                                } else {
-                                    // If not in memory, try to load from file
-                                    if let Some(db) = db::DbInstance::load_from_file(db_name) {
-                                        let db_clone = db.clone();
-                                        dbs.insert(db_name.to_string(), db);
-                                        Some(db_clone)
-                                    } else {
-                                        None
-                                    }
-                                }
-                            };
+                            let db_name = parts[1];
+                            let db_instance = find_db(&all_dbs, db_name).await;
 
                             match db_instance {
                                 Some(db_instance) => {
@@ -212,146 +1544,207 @@ async fn main() -> anyhow::Result<()> {
                                         // Ask for authentication
                                         let mut authenticated = false;
                                         let mut auth_attempts = 0;
-                                        const MAX_AUTH_ATTEMPTS: u8 = 3; // 3 attempts max
 
                                         while !authenticated && auth_attempts < MAX_AUTH_ATTEMPTS {
                                             auth_attempts += 1;
 
-                                            if let Err(e) = writer.write_all(b"Username:\n").await {
-                                                eprintln!("Error writing to socket: {}", e);
-                                                break;
-                                            }
-
-                                            let mut username_line = String::new();
-                                            if let Err(e) =
-                                                reader.read_line(&mut username_line).await
-                                            {
-                                                eprintln!("Error reading username: {}", e);
-                                                break;
-                                            }
-                                            let username = username_line.trim();
-
-                                            if let Err(e) = writer.write_all(b"Password:\n").await {
-                                                eprintln!("Error writing to socket: {}", e);
-                                                break;
-                                            }
-
-                                            let mut password_line = String::new();
-                                            if let Err(e) =
-                                                reader.read_line(&mut password_line).await
-                                            {
-                                                eprintln!("Error reading password: {}", e);
-                                                break;
-                                            }
-                                            let password = password_line.trim();
-                                            let is_valid = match verify(password, db_instance.password.as_deref().unwrap_or("")) {
+                                            let Some(username) = ask(&mut reader, &mut writer, "Username:", idle_timeout).await else {
+                                                break 'session;
+                                            };
+                                            let Some(password) = ask(&mut reader, &mut writer, "Password:", idle_timeout).await else {
+                                                break 'session;
+                                            };
+                                            let is_valid = match verify(&password, db_instance.password.as_deref().unwrap_or("")) {
                                                 Ok(valid) => valid,
                                                 Err(e) => {
-                                                    eprintln!("Error verifying password: {}", e);
-                                                    if let Err(e) = writer.write_all(b"Authentication error.\n").await {
-                                                        eprintln!("Error writing to socket: {}", e);
+                                                    log_error(&format!("Error verifying password: {}", e));
+                                                    if let Err(e) = writer.write_reply(&Reply::error("Authentication error.")).await {
+                                                        log_warn(&format!("Error writing to socket: {}", e));
                                                     }
                                                     break;
                                                 }
                                             };
                                             
-                                            if db_instance.username.as_deref() == Some(username) && is_valid
+                                            if db_instance.username.as_deref() == Some(username.as_str()) && is_valid
                                             {
                                                 // If authentication successful, select database
                                                 authenticated = true;
-                                                current_db_instance =
-                                                    Some(Arc::new(db_instance.clone()));
-                                                if let Err(e) = writer.write_all(format!("Authentication successful Using database '{}'\n", db_name).as_bytes()).await {
-                                                    eprintln!("Error writing to socket: {}", e);
+                                                current_db_instance = Some(db_instance.clone());
+                                                if let Err(e) = writer.write_reply(&Reply::status(format!("Authentication successful Using database '{}'", db_name))).await {
+                                                    log_warn(&format!("Error writing to socket: {}", e));
                                                     break;
                                                 }
                                             } else {
                                                 // If authentication failed, try again
-                                                if let Err(e) = writer
-                                                    .write_all(
-                                                        b"Authentication failed. Try again.\n",
-                                                    )
-                                                    .await
-                                                {
-                                                    eprintln!("Error writing to socket: {}", e);
+                                                if let Err(e) = writer.write_reply(&Reply::error("Authentication failed. Try again.")).await {
+                                                    log_warn(&format!("Error writing to socket: {}", e));
                                                     break;
                                                 }
                                             }
                                         }
                                         // If authentication failed after max attempts, disconnect
                                         if !authenticated && auth_attempts >= MAX_AUTH_ATTEMPTS {
-                                            if let Err(e) = writer.write_all(b"Too many failed authentication attempts. Disconnecting.\n").await {
-                                                eprintln!("Error writing to socket: {}", e);
+                                            if let Err(e) = writer.write_reply(&Reply::error("Too many failed authentication attempts. Disconnecting.")).await {
+                                                log_warn(&format!("Error writing to socket: {}", e));
                                             }
                                             break;
                                         }
                                     } else {
                                         // If authentication is not required, select database
-                                        current_db_instance = Some(Arc::new(db_instance.clone()));
-                                        if let Err(e) = writer
-                                            .write_all(
-                                                format!("Using database '{}'\n", db_name)
-                                                    .as_bytes(),
-                                            )
-                                            .await
-                                        {
-                                            eprintln!("Error writing to socket: {}", e);
+                                        current_db_instance = Some(db_instance.clone());
+                                        if let Err(e) = writer.write_reply(&Reply::status(format!("Using database '{}'", db_name))).await {
+                                            log_warn(&format!("Error writing to socket: {}", e));
                                             break;
                                         }
                                     }
                                 }
                                 None => {
-                                    if let Err(e) = writer
-                                        .write_all(
-                                            format!("Database '{}' not found\n", db_name)
-                                                .as_bytes(),
-                                        )
-                                        .await
+                                    if let Err(e) = writer.write_reply(&Reply::error(format!("Database '{}' not found", db_name))).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // Release the selected database so create and use work again
+                    "DESELECT" if parts.len() == 1 => {
+                        let response = match current_db_instance.take() {
+                            Some(previous) => {
+                                flush_blocking(previous.clone()).await;
+                                Reply::status(format!("Database '{}' deselected", previous.name))
+                            }
+                            None => Reply::error("No database selected"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Start queueing statements for an all-or-nothing EXEC
+                    "MULTI" if parts.len() == 1 => {
+                        let response = if transaction.is_some() {
+                            Reply::error("Error: MULTI calls can not be nested")
+                        } else if current_db_instance.is_none() {
+                            Reply::error("No database selected")
+                        } else {
+                            transaction = Some(Vec::new());
+                            transaction_failed = false;
+                            Reply::ok()
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Run the queued statements without other connections interleaving
+                    "EXEC" if parts.len() == 1 => {
+                        let response = match transaction.take() {
+                            Some(_) if transaction_failed => {
+                                Reply::error("Error: Transaction discarded because of previous errors")
+                            }
+                            Some(queued) => Reply::Array(parser::exec_transaction(
+                                &queued,
+                                &current_db_instance,
+                                writer.length_prefixed,
+                            )),
+                            None => Reply::error("Error: EXEC without MULTI"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Drop the queued statements
+                    "DISCARD" if parts.len() == 1 => {
+                        let response = match transaction.take() {
+                            Some(_) => Reply::ok(),
+                            None => Reply::error("Error: DISCARD without MULTI"),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
+                        }
+                    }
+                    // Authenticate and select a database in one line, without prompts
+                    "AUTH" if parts.len() == 3 || parts.len() == 4 => {
+                        // Without a database name, check against the selected database
+                        let (target, username, password) = if parts.len() == 4 {
+                            let target = find_db(&all_dbs, parts[1])
+                                .await
+                                .ok_or_else(|| format!("Database '{}' not found", parts[1]));
+                            (target, parts[2], parts[3])
+                        } else {
+                            let target = current_db_instance.clone().ok_or_else(|| "No database selected".to_string());
+                            (target, parts[1], parts[2])
+                        };
+                        let response = match target {
+                            Ok(db_instance) if db_instance.require_auth => {
+                                let is_valid = match verify(password, db_instance.password.as_deref().unwrap_or("")) {
+                                    Ok(valid) => valid,
+                                    Err(e) => {
+                                        log_error(&format!("Error verifying password: {}", e));
+                                        false
+                                    }
+                                };
+
+                                if db_instance.username.as_deref() == Some(username) && is_valid {
+                                    // Like SELECT, save the database being switched away from
+                                    if let Some(previous) = &current_db_instance
+                                        && !Arc::ptr_eq(previous, &db_instance)
                                     {
-                                        eprintln!("Error writing to socket: {}", e);
+                                        flush_blocking(previous.clone()).await;
+                                    }
+                                    current_db_instance = Some(db_instance);
+                                    failed_auths = 0;
+                                    Reply::ok()
+                                } else {
+                                    failed_auths += 1;
+                                    if failed_auths >= MAX_AUTH_ATTEMPTS {
+                                        if let Err(e) = writer.write_reply(&Reply::error("Too many failed authentication attempts. Disconnecting.")).await {
+                                            log_warn(&format!("Error writing to socket: {}", e));
+                                        }
                                         break;
                                     }
+                                    Reply::error("Error: Authentication failed")
                                 }
                             }
+                            Ok(db_instance) => Reply::error(format!(
+                                "Error: Database '{}' does not require authentication, use SELECT",
+                                db_instance.name
+                            )),
+                            Err(e) => Reply::error(e),
+                        };
+                        if let Err(e) = writer.write_reply(&response).await {
+                            log_warn(&format!("Error writing to socket: {}", e));
+                            break;
                         }
                     }
                     // Drop (delete) a database
                     "drop" if parts.len() == 2 => {
                         let db_name = parts[1].to_string();
-
-                        // Check if trying to drop the currently selected database
-                        if let Some(ref current_db) = current_db_instance {
-                            if current_db.name == db_name {
-                                if let Err(e) = writer.write_all(
-                    b"Cannot drop the currently selected database. Please 'use' another database first.\n"
-                ).await {
-                    eprintln!("Error writing to socket: {}", e);
-                    break;
-                }
-                                continue;
+                        if let Err(e) = db::validate_db_name(&db_name) {
+                            if let Err(e) = writer.write_reply(&Reply::error(e)).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
                             }
+                            continue;
                         }
 
-                        // First check if database exists without holding the lock across await
-                        let (db_instance, exists_in_memory) = {
-                            let mut dbs = all_dbs.lock().unwrap();
-                            if let Some(db) = dbs.remove(&db_name) {
-                                (Some(db), true)
-                            } else {
-                                (None, false)
+                        // Check if trying to drop the currently selected database
+                        if let Some(ref current_db) = current_db_instance
+                            && current_db.name == db_name
+                        {
+                            if let Err(e) = writer.write_reply(&Reply::error("Cannot drop the currently selected database. Please 'use' another database first.")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
                             }
-                        };
+                            continue;
+                        }
 
-                        // Handle file-based database case
-                        let (db_instance, exists_in_memory) = if db_instance.is_none() {
-                            if Path::new(&format!("dbs/{}.json", db_name)).exists() {
-                                (db::DbInstance::load_from_file(&db_name), false)
-                            } else {
-                                (None, false)
-                            }
-                        } else {
-                            (db_instance, exists_in_memory)
-                        };
+                        // The database stays in `all_dbs` until the drop succeeds, so
+                        // sessions using it meanwhile still share the same instance
+                        let db_instance = find_db(&all_dbs, &db_name).await;
 
                         match db_instance {
                             Some(db_instance) => {
@@ -362,114 +1755,71 @@ async fn main() -> anyhow::Result<()> {
                                 if require_auth {
                                     let mut authenticated = false;
                                     let mut auth_attempts = 0;
-                                    const MAX_AUTH_ATTEMPTS: u8 = 3;
 
                                     while !authenticated && auth_attempts < MAX_AUTH_ATTEMPTS {
                                         auth_attempts += 1;
 
-                                        if let Err(e) = writer.write_all(b"Username:\n").await {
-                                            eprintln!("Error writing to socket: {}", e);
-                                            break;
-                                        }
-
-                                        let mut username_line = String::new();
-                                        if let Err(e) = reader.read_line(&mut username_line).await {
-                                            eprintln!("Error reading username: {}", e);
-                                            break;
-                                        }
-                                        let input_username = username_line.trim();
-
-                                        if let Err(e) = writer.write_all(b"Password:\n").await {
-                                            eprintln!("Error writing to socket: {}", e);
-                                            break;
-                                        }
-
-                                        let mut password_line = String::new();
-                                        if let Err(e) = reader.read_line(&mut password_line).await {
-                                            eprintln!("Error reading password: {}", e);
-                                            break;
-                                        }
-                                        let input_password = password_line.trim();
-                                        let is_valid = match verify(input_password, db_instance.password.as_deref().unwrap_or("")) {
+                                        let Some(input_username) = ask(&mut reader, &mut writer, "Username:", idle_timeout).await else {
+                                            break 'session;
+                                        };
+                                        let Some(input_password) = ask(&mut reader, &mut writer, "Password:", idle_timeout).await else {
+                                            break 'session;
+                                        };
+                                        let is_valid = match verify(&input_password, db_instance.password.as_deref().unwrap_or("")) {
                                             Ok(valid) => valid,
                                             Err(e) => {
-                                                eprintln!("Error verifying password: {}", e);
-                                                if let Err(e) = writer.write_all(b"Authentication error.\n").await {
-                                                    eprintln!("Error writing to socket: {}", e);
+                                                log_error(&format!("Error verifying password: {}", e));
+                                                if let Err(e) = writer.write_reply(&Reply::error("Authentication error.")).await {
+                                                    log_warn(&format!("Error writing to socket: {}", e));
                                                 }
                                                 break;
                                             }
                                         };
                                         
-                                        if db_instance.username.as_deref() == Some(input_username) && is_valid
+                                        if db_instance.username.as_deref() == Some(input_username.as_str()) && is_valid
                                         {
                                             authenticated = true;
                                         } else {
-                                            if let Err(e) = writer
-                                                .write_all(b"Authentication failed. Try again.\n")
-                                                .await
-                                            {
-                                                eprintln!("Error writing to socket: {}", e);
+                                            if let Err(e) = writer.write_reply(&Reply::error("Authentication failed. Try again.")).await {
+                                                log_warn(&format!("Error writing to socket: {}", e));
                                                 break;
                                             }
                                         }
                                     }
 
                                     if !authenticated {
-                                        // Reinsert if it was in memory
-                                        if exists_in_memory {
-                                            let mut dbs = all_dbs.lock().unwrap();
-                                            dbs.insert(db_name.clone(), db_instance);
+                                        if let Err(e) = writer.write_reply(&Reply::error("Too many failed authentication attempts. Operation aborted.")).await {
+                                            log_warn(&format!("Error writing to socket: {}", e));
                                         }
-                                        if let Err(e) = writer.write_all(
-                            b"Too many failed authentication attempts. Operation aborted.\n"
-                        ).await {
-                            eprintln!("Error writing to socket: {}", e);
-                        }
                                         continue;
                                     }
                                 }
 
-                                // Delete the database file
-                                let path = format!("dbs/{}.json", db_name);
-                                if let Err(e) = std::fs::remove_file(&path) {
-                                    // Reinsert if it was in memory
-                                    if exists_in_memory {
-                                        let mut dbs = all_dbs.lock().unwrap();
-                                        dbs.insert(db_name.clone(), db_instance);
-                                    }
-                                    if let Err(e) = writer
-                                        .write_all(
-                                            format!("Error deleting database file: {}\n", e)
-                                                .as_bytes(),
-                                        )
-                                        .await
-                                    {
-                                        eprintln!("Error writing to socket: {}", e);
+                                // Unlist it first so no session loads it again, then delete its
+                                // files. Sessions still using it deselect it once it is dropped
+                                all_dbs.lock().unwrap().remove(&db_name);
+                                let dropping = db_instance.clone();
+                                let dropped = tokio::task::spawn_blocking(move || dropping.drop_files())
+                                    .await
+                                    .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+                                if let Err(e) = dropped {
+                                    // Keep the database, unless another session loaded it again
+                                    all_dbs.lock().unwrap().entry(db_name.clone()).or_insert(db_instance);
+                                    if let Err(e) = writer.write_reply(&Reply::error(format!("Error deleting database file: {}", e))).await {
+                                        log_warn(&format!("Error writing to socket: {}", e));
                                         break;
                                     }
                                     continue;
                                 }
 
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Database '{}' deleted successfully\n", db_name)
-                                            .as_bytes(),
-                                    )
-                                    .await
-                                {
-                                    eprintln!("Error writing to socket: {}", e);
+                                if let Err(e) = writer.write_reply(&Reply::status(format!("Database '{}' deleted successfully", db_name))).await {
+                                    log_warn(&format!("Error writing to socket: {}", e));
                                     break;
                                 }
                             }
                             None => {
-                                if let Err(e) = writer
-                                    .write_all(
-                                        format!("Database '{}' not found\n", db_name).as_bytes(),
-                                    )
-                                    .await
-                                {
-                                    eprintln!("Error writing to socket: {}", e);
+                                if let Err(e) = writer.write_reply(&Reply::error(format!("Database '{}' not found", db_name))).await {
+                                    log_warn(&format!("Error writing to socket: {}", e));
                                     break;
                                 }
                             }
@@ -477,28 +1827,189 @@ async fn main() -> anyhow::Result<()> {
                     }
                     // All other commands
                     _ => {
-                        match &current_db_instance {
-                            Some(_db) => {
-                                // Parse command and execute
-                                let response =
-                                    parser::parse_statement(line.trim(), &current_db_instance);
-                                if let Err(e) =
-                                    writer.write_all(format!("{}\n", response).as_bytes()).await
-                                {
-                                    eprintln!("Error writing to socket: {}", e);
-                                    break;
-                                }
+                        // Reject admin-only commands from regular sessions
+                        if !is_admin && parser::is_admin_command(command) {
+                            if let Err(e) = writer.write_reply(&Reply::error("Error: Admin privileges required")).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
                             }
-                            None => {
-                                if let Err(e) = writer.write_all(b"Unknown command.\n").await {
-                                    eprintln!("Error writing to socket: {}", e);
-                                    break;
-                                }
+                            continue;
+                        }
+                        // Database-free statements run with or without a selection
+                        if !parser::is_statement(command) {
+                            if let Err(e) = writer.write_reply(&Reply::error(unknown_command(command))).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
+                            }
+                        } else if current_db_instance.is_some() || !parser::needs_database(command) {
+                            // Parse command and execute
+                            let response =
+                                parser::parse_statement(command, &current_db_instance, writer.length_prefixed);
+                            if let Err(e) = writer.write_reply(&response).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
+                            }
+                        } else {
+                            let response = Reply::error(format!(
+                                "No database selected, run 'use <dbname>' before {}",
+                                parser::command_name(command)
+                            ));
+                            if let Err(e) = writer.write_reply(&response).await {
+                                log_warn(&format!("Error writing to socket: {}", e));
+                                break;
                             }
                         }
                     }
                 }
             }
+            metrics.connections_active.fetch_sub(1, Ordering::Relaxed);
         });
     }
+
+    // Stop accepting connections before the final save
+    drop(listener);
+    drop(unix_listener);
+    #[cfg(unix)]
+    if let Some(path) = &config.unix_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    log_info("🛑 Shutdown signal received, saving all databases");
+    save_all(&all_dbs);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn databases_are_created_used_and_dropped() {
+        let all_dbs: DbMap = Arc::new(Mutex::new(HashMap::new()));
+        let created = db::test_instance();
+        let name = created.name.clone();
+        created.sync_to_file().unwrap();
+        drop(created);
+
+        // Loaded from its file once, then shared by every session
+        let used = find_db(&all_dbs, &name).await.expect("database loads");
+        assert_eq!(used.name, name);
+        let again = find_db(&all_dbs, &name).await.unwrap();
+        assert!(Arc::ptr_eq(&used, &again));
+
+        all_dbs.lock().unwrap().remove(&name);
+        used.drop_files().unwrap();
+        assert!(!db::db_path(&name).exists());
+        assert!(find_db(&all_dbs, &name).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn find_db_rejects_names_outside_the_data_directory() {
+        let all_dbs: DbMap = Arc::new(Mutex::new(HashMap::new()));
+        let saved = db::test_instance();
+        saved.sync_to_file().unwrap();
+
+        // Files the names below would reach if they were joined to the path
+        let dir_name = db::data_dir().file_name().unwrap().to_str().unwrap().to_string();
+        let nested = db::data_dir().join("a");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::copy(db::db_path(&saved.name), nested.join("b.json")).unwrap();
+        std::fs::copy(db::db_path(&saved.name), db::db_path("")).unwrap();
+        let overlong = "x".repeat(65);
+        std::fs::copy(db::db_path(&saved.name), db::db_path(&overlong)).unwrap();
+
+        let escaping = format!("../{}/{}", dir_name, saved.name);
+        for name in ["../x", escaping.as_str(), "a/b", "", "x\0", overlong.as_str()] {
+            assert!(find_db(&all_dbs, name).await.is_none(), "{:?} was found", name);
+        }
+        assert!(all_dbs.lock().unwrap().is_empty());
+        assert!(find_db(&all_dbs, &saved.name).await.is_some());
+    }
+
+    #[test]
+    fn seeds_are_stored_like_set_statements() {
+        let all_dbs: DbMap = Arc::new(Mutex::new(HashMap::new()));
+        let db_instance = Arc::new(db::test_instance());
+        let name = db_instance.name.clone();
+        all_dbs.lock().unwrap().insert(name.clone(), db_instance.clone());
+        let db = Some(db_instance.clone());
+        assert_eq!(parser::parse_statement(r#"SET("kept","old")"#, &db, false), Reply::ok());
+        db_instance.set_default_ttl(Some(Duration::from_secs(60)));
+        db_instance.set_quota(Some(64));
+
+        let seeds = |pairs: &[(&str, &str)]| {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let quoted = r#"say "hi", then \ leave"#;
+        seed(
+            &all_dbs,
+            &name,
+            seeds(&[("greeting", quoted), ("kept", "new"), ("", "x"), ("big", &"x".repeat(100))]),
+            false,
+        );
+        let get = |key: &str| parser::parse_statement(&format!("GET({})", parser::quote(key)), &db, false);
+        assert_eq!(get("greeting"), Reply::bulk(quoted));
+        assert!(matches!(parser::parse_statement(r#"TTL("greeting")"#, &db, false), Reply::Integer(59..=60)));
+        assert_eq!(get("kept"), Reply::bulk("old"));
+        // Over the quota, so refused as a SET would be
+        assert!(matches!(get("big"), Reply::Error(_)));
+
+        seed(&all_dbs, &name, seeds(&[("kept", "new")]), true);
+        assert_eq!(get("kept"), Reply::bulk("new"));
+    }
+
+    #[test]
+    fn commands_lists_what_the_session_may_run() {
+        let has = |names: &[String], name: &str| names.iter().any(|n| n == name);
+        let user = available_commands(false, false);
+        assert!(has(&user, "GET") && has(&user, "create") && has(&user, "COMMANDS"));
+        assert!(!has(&user, "ADMIN"));
+        for admin_only in ["METRICS", "CLEANNOW", "PAUSEEXPIRY", "SETQUOTA", "MAPVALUES"] {
+            assert!(!has(&user, admin_only), "{} is listed", admin_only);
+        }
+
+        // ADMIN is offered once it is enabled, admin-only commands after using it
+        assert!(has(&available_commands(false, true), "ADMIN"));
+        let admin = available_commands(true, true);
+        for admin_only in ["METRICS", "CLEANNOW", "PAUSEEXPIRY", "SETQUOTA", "MAPVALUES"] {
+            assert!(has(&admin, admin_only), "{} is missing", admin_only);
+        }
+        assert_eq!(admin.len(), SESSION_COMMANDS.len() + parser::statement_names(true).len());
+    }
+
+    #[test]
+    fn secrets_compare_equal_only_when_identical() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret!"));
+        assert!(!constant_time_eq(b"secret!", b"secret"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn config_files_hold_known_settings_only() {
+        let settings = parse_config_file("# Sweep often\n\nCLEAN_INTERVAL = 2\nLOG_LEVEL=debug\n").unwrap();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings["CLEAN_INTERVAL"], "2");
+        assert_eq!(settings["LOG_LEVEL"], "debug");
+        assert!(parse_config_file("CLEAN_INTERVAL 2").is_err());
+        assert!(parse_config_file("ADMIN_PASSWORD=secret").is_err());
+    }
+
+    #[test]
+    fn reloading_a_config_file_applies_the_clean_interval() {
+        let path = std::env::temp_dir().join(format!("db-server-reload-{}.conf", std::process::id()));
+        let (interval_updates, intervals) = watch::channel(Duration::from_secs(5));
+
+        std::fs::write(&path, "CLEAN_INTERVAL=60\nMAX_KEYS=10\n").unwrap();
+        let settings = reload_config(&path, &HashMap::new(), &interval_updates).unwrap();
+        assert_eq!(*intervals.borrow(), Duration::from_secs(60));
+        assert_eq!(settings["MAX_KEYS"], "10");
+
+        // An invalid file changes nothing
+        std::fs::write(&path, "CLEAN_INTERVAL=30\nLOG_LEVEL=loud\n").unwrap();
+        assert!(reload_config(&path, &settings, &interval_updates).is_err());
+        assert_eq!(*intervals.borrow(), Duration::from_secs(60));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,57 @@
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Server-wide counters, shared by every connection.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // Connections accepted since startup
+    pub connections_total: AtomicU64,
+    // Connections currently open
+    pub connections_active: AtomicU64,
+    // Commands received since startup
+    pub commands_total: AtomicU64,
+}
+
+// Type alias for the metrics handle passed to each connection.
+pub type SharedMetrics = Arc<Metrics>;
+
+impl Metrics {
+    /// Renders the counters as a single-line JSON object. Field names are
+    /// part of the protocol and must stay stable.
+    pub fn to_json(&self, databases_loaded: usize) -> String {
+        json!({
+            "connections_total": self.connections_total.load(Ordering::Relaxed),
+            "connections_active": self.connections_active.load(Ordering::Relaxed),
+            "commands_total": self.commands_total.load(Ordering::Relaxed),
+            "databases_loaded": databases_loaded,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_has_the_documented_fields() {
+        let metrics = Metrics::default();
+        metrics.connections_total.store(3, Ordering::Relaxed);
+        metrics.connections_active.store(1, Ordering::Relaxed);
+        metrics.commands_total.store(42, Ordering::Relaxed);
+
+        let json: serde_json::Value = serde_json::from_str(&metrics.to_json(2)).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "connections_total": 3,
+                "connections_active": 1,
+                "commands_total": 42,
+                "databases_loaded": 2,
+            })
+        );
+        // A single line, so it is one reply line
+        assert!(!metrics.to_json(2).contains('\n'));
+    }
+}
@@ -1,21 +1,25 @@
 // =======================================================
 // 🧠 INFO: Imports
 // =======================================================
-use crate::db::{DbInstance, ValueWithExpiry};
+use crate::db::{fnv1a_64, unix_millis, DbInstance, Value, ValueWithExpiry};
+use crate::logger::log_info_with;
+use crate::reply::Reply;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Parses duration string (e.g. "5s", "10m", "1d") into Duration
 /// Format: <number><unit> where unit is s (seconds), m (minutes), or d (days)
 /// Returns error string if format is invalid
-fn parse_duration(s: &str) -> Result<Duration, String> {
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
     if s.is_empty() {
         return Err("Empty TTL provided".to_string());
     }
 
     // Split into numeric and unit parts
-    let num_part: String = s.chars().take_while(|c| c.is_digit(10)).collect();
-    let unit_part: String = s.chars().skip_while(|c| c.is_digit(10)).collect();
+    let num_part: String = s.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let unit_part: String = s.chars().skip_while(|c| c.is_ascii_digit()).collect();
 
     let num = num_part.parse::<u64>().map_err(|_| "Invalid TTL number".to_string())?;
 
@@ -27,102 +31,2791 @@ fn parse_duration(s: &str) -> Result<Duration, String> {
     }
 }
 
+/// Largest number of keys BIGKEYS will report
+const MAX_BIGKEYS: usize = 1000;
+
+/// Keys a SCAN batch looks at unless told otherwise
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Most keys a single SCAN batch may look at
+const MAX_SCAN_COUNT: usize = 1000;
+
+/// Longest value SETRANGE may produce (64 MiB), so a large offset can't
+/// allocate unbounded memory
+const MAX_SETRANGE_LEN: usize = 64 * 1024 * 1024;
+
+/// Upper bound for a TTL set with EXPIRENX or EXPIREALLAT, or grown with
+/// EXPIREADD (365 days)
+const MAX_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+/// Reply for commands run against a key holding a different kind of value
+const WRONG_TYPE: &str = "Error: Operation against a key holding the wrong kind of value";
+
+/// Reply for SETRANGE and GETRANGE offsets that fall inside a multi-byte
+/// UTF-8 character of a string value
+const SPLIT_CHARACTER: &str = "Error: Offset splits a UTF-8 character";
+
+/// Matches a key against a glob pattern.
+/// Supports `*` (any run of characters) and `?` (exactly one character).
+pub fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+    // Position of the last `*` seen and the key index it was tried against
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((star_p, star_k)) = star {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            k = star_k + 1;
+            star = Some((star_p, star_k + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Any trailing `*`s match the empty remainder
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Splits the arguments of a statement on commas. Commas inside double
+/// quotes belong to the argument, so values may contain them. Each argument
+/// is trimmed and unquoted with `unquote`; `""` is an empty argument.
+fn split_args(content: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            // Keep escapes for unquote, so an escaped quote doesn't close the string
+            '\\' if in_quotes => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            ',' if !in_quotes => args.push(unquote(&std::mem::take(&mut current))),
+            _ => current.push(c),
+        }
+    }
+    args.push(unquote(&current));
+    args
+}
+
+/// Splits a line into the statements separated by `;`. Semicolons inside
+/// double quotes are part of the statement. Empty statements are dropped.
+pub fn split_statements(line: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            '\\' if in_quotes => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            ';' if !in_quotes => statements.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    statements.push(current);
+
+    statements
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Trims an argument and, if it is double-quoted, strips the quotes and
+/// resolves the escapes `\"` (quote) and `\\` (backslash). Other
+/// backslashes are kept as they are.
+pub fn unquote(arg: &str) -> String {
+    let arg = arg.trim();
+    let Some(quoted) = arg.strip_prefix('"') else {
+        return arg.to_string();
+    };
+
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => value.push(escaped),
+                Some(other) => {
+                    value.push(c);
+                    value.push(other);
+                }
+                None => value.push(c),
+            },
+            // The closing quote ends the argument
+            '"' => break,
+            _ => value.push(c),
+        }
+    }
+    value
+}
+
+/// Quotes an argument for the statement parser, escaping `"` and `\`, so
+/// `unquote` gives it back unchanged.
+pub fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the bytes of `value` from `start` to `end` inclusive, where
+/// negative offsets count from the end. Offsets past either end are clamped,
+/// so a range entirely outside the value is empty.
+fn byte_range(value: &[u8], start: i64, end: i64) -> &[u8] {
+    let len = value.len() as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end {
+        return &[];
+    }
+    &value[start as usize..=end as usize]
+}
+
+/// Parses the offsets of GETRANGE.
+fn range_offsets(start: &str, end: &str) -> Result<(i64, i64), Reply> {
+    match (start.parse::<i64>(), end.parse::<i64>()) {
+        (Ok(start), Ok(end)) => Ok((start, end)),
+        _ => Err(Reply::error("Error: start and end must be integers")),
+    }
+}
+
+/// Adds `delta` to the integer stored at `key` under a single lock, creating
+/// the key at 0 with the default TTL if it is missing. The key keeps any TTL
+/// it already had.
+/// Returns the new value or an error message.
+fn increment_by(db_instance: &DbInstance, key: &str, delta: i64) -> Reply {
+    let result = {
+        let mut db = db_instance.data.write(&[key]);
+        let current = match db.get(key) {
+            Some(val) if !val.is_expired() => match val.value.as_str().map(str::parse::<i64>) {
+                Some(Ok(n)) => Some(n),
+                Some(Err(_)) => return Reply::error("Error: value is not an integer"),
+                None => return Reply::error(WRONG_TYPE),
+            },
+            _ => None,
+        };
+
+        match current.unwrap_or(0).checked_add(delta) {
+            Some(updated) => {
+                let updated_str = updated.to_string();
+                // A longer number can push the database over its quota too
+                if let Err(e) = db_instance.check_quota(&db, key, updated_str.len()) {
+                    return Reply::error(e);
+                }
+                match db.get_mut(key) {
+                    Some(val) if current.is_some() => val.value = updated_str.into(),
+                    _ => {
+                        db.insert(key.to_string(), ValueWithExpiry::new(updated_str, db_instance.default_ttl()));
+                    }
+                }
+                Ok(updated)
+            }
+            None => Err("Error: increment or decrement would overflow".to_string()),
+        }
+    };
+
+    match result {
+        Ok(updated) => {
+            db_instance.persist();
+            Reply::Integer(updated)
+        }
+        Err(e) => Reply::error(e),
+    }
+}
+
+/// Every statement handled by `parse_statement`, paired with whether it is
+/// admin-only. Admin-only statements can rewrite large parts of a database
+/// and are only available to sessions that have authenticated with `ADMIN`.
+/// New statements must be registered here to be listed by `COMMANDS`.
+const STATEMENTS: &[(&str, bool)] = &[
+    ("SET", false),
+    ("SETMETA", false),
+    ("SETNX", false),
+    ("MSET", false),
+    ("MSETNX", false),
+    ("GET", false),
+    ("MGET", false),
+    ("TOUCH", false),
+    ("STRLEN", false),
+    ("GETRANGE", false),
+    ("TYPE", false),
+    ("TAGS", false),
+    ("KEYSBYTAG", false),
+    ("TAGCOUNT", false),
+    ("APPEND", false),
+    ("SETRANGE", false),
+    ("INCR", false),
+    ("DECR", false),
+    ("INCRBY", false),
+    ("DECRBY", false),
+    ("INCRBYFLOAT", false),
+    ("GETSET", false),
+    ("GETDEL", false),
+    ("GETORSET", false),
+    ("GETRESET", false),
+    ("DEL", false),
+    ("RENAME", false),
+    ("COPY", false),
+    ("LPUSH", false),
+    ("RPUSH", false),
+    ("LPOP", false),
+    ("RPOP", false),
+    ("LLEN", false),
+    ("LRANGE", false),
+    ("HSET", false),
+    ("HGET", false),
+    ("HGETALL", false),
+    ("HDEL", false),
+    ("SADD", false),
+    ("SREM", false),
+    ("SMEMBERS", false),
+    ("SISMEMBER", false),
+    ("TTL", false),
+    ("KEYSTTL", false),
+    ("EXPIRENX", false),
+    ("EXPIREADD", false),
+    ("EXPIREALLAT", false),
+    ("RECENTKEYS", false),
+    ("PURGE", false),
+    ("DBSIZE", false),
+    ("RANDOMKEY", false),
+    ("SCAN", false),
+    ("FSYNC", false),
+    ("SHARD", false),
+    ("QUOTA", false),
+    ("SETQUOTA", true),
+    ("DEFAULTTTL", false),
+    ("SETDEFAULTTTL", true),
+    ("BIGKEYS", true),
+    ("MAPVALUES", true),
+];
+
+/// Statements that don't touch a database and can run before `use`.
+const DATABASE_FREE_STATEMENTS: &[&str] = &["SHARD"];
+
+/// Returns true if the statement is an admin-only command
+pub fn is_admin_command(input: &str) -> bool {
+    let input = input.trim();
+    STATEMENTS.iter().any(|(name, admin_only)| {
+        *admin_only
+            && input
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with('('))
+    })
+}
+
+/// Returns true if the input is a statement handled by `parse_statement`
+pub fn is_statement(input: &str) -> bool {
+    let input = input.trim();
+    STATEMENTS.iter().any(|(name, _)| {
+        input
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('('))
+    })
+}
+
+/// Returns false for statements that can run without a selected database
+pub fn needs_database(input: &str) -> bool {
+    let input = input.trim();
+    !DATABASE_FREE_STATEMENTS.iter().any(|name| {
+        input
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with('('))
+    })
+}
+
+/// Returns the command name a line starts with: everything before the
+/// first `(` or whitespace.
+pub fn command_name(input: &str) -> &str {
+    let input = input.trim();
+    let end = input
+        .find(|c: char| c == '(' || c.is_whitespace())
+        .unwrap_or(input.len());
+    &input[..end]
+}
+
+/// Longest command name echoed back by `unknown_command`
+const MAX_ECHOED_NAME_CHARS: usize = 64;
+
+/// Reply for a line that no statement accepted. Names what was sent, and
+/// tells a known statement with malformed arguments apart from a typo.
+pub fn unknown_command(input: &str) -> String {
+    let name = command_name(input);
+    if STATEMENTS.iter().any(|(statement, _)| *statement == name) {
+        return format!("Error: Invalid syntax for {}, see HELP {}", name, name);
+    }
+    let name: String = name.chars().take(MAX_ECHOED_NAME_CHARS).collect();
+    format!("Unknown command '{}', see HELP", name)
+}
+
+/// Names of the statements available to a session with the given privileges.
+pub fn statement_names(is_admin: bool) -> Vec<String> {
+    STATEMENTS
+        .iter()
+        .filter(|(_, admin_only)| is_admin || !admin_only)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
 // =======================================================
 // 🧠 INFO: Main Command Parser
 // =======================================================
 /// Parses and executes database commands
 /// Supported commands:
-/// - SET("key","value",["ttl"]) - Stores key-value pair with optional TTL
+/// - SET("key","value",["ttl"]) - Stores key-value pair with optional TTL ("" for none, the default TTL if omitted)
+/// - SETMETA("key","value","ttl","tag"...) - Stores key-value pair with TTL ("" for none) and tags
+/// - SETNX("key","value",["ttl"]) - Sets a key only if it does not exist
+/// - MSET("k1","v1","k2","v2",...) - Sets several pairs at once
+/// - MSETNX("k1","v1","k2","v2",...) - Sets all pairs only if none of the keys exist
 /// - GET("key") - Retrieves value for key
+/// - MGET("k1","k2",...) - Retrieves several values in order, (nil) for missing keys
+/// - TOUCH("k1","k2",...) - Counts the keys that exist, removing expired ones it finds
+/// - STRLEN("key") - Returns the byte length of a value (0 if missing)
+/// - GETRANGE("key","start","end") - Returns the bytes between two inclusive offsets (negative counts from the end)
+/// - TYPE("key") - Returns the kind of value stored at a key, or none
+/// - TAGS("key") - Lists the tags attached to a key
+/// - KEYSBYTAG("tag") - Lists keys carrying a tag
+/// - TAGCOUNT("tag") - Counts keys carrying a tag
+/// - APPEND("key","suffix") - Appends to a value and returns the new length
+/// - SETRANGE("key","offset","value") - Overwrites bytes from an offset, zero-padding, and returns the new length
+/// - INCR("key") / DECR("key") - Adds or subtracts one from an integer value
+/// - INCRBY("key","n") / DECRBY("key","n") - Adds or subtracts n from an integer value
+/// - INCRBYFLOAT("key","n") - Adds a floating point n to a numeric value
+/// - GETSET("key","value") - Stores a value and returns the previous one; the new value gets the default TTL
+/// - GETDEL("key") - Returns a value and deletes the key
+/// - GETORSET("key","default",["ttl"]) - Returns the value, storing the default first if absent (same TTL rules as SET)
+/// - GETRESET("key") - Returns an integer counter and resets it to 0
 /// - DEL("key") - Deletes key
-pub fn parse_statement(input: &str,  current_db_instance: &Option<Arc<DbInstance>> ) -> String {
+/// - RENAME("old","new") - Moves a value and its TTL to a new key
+/// - COPY("src","dst",["replace"]) - Copies a value and its TTL to a new key, returns 1 if copied
+/// - LPUSH("key","value",...) / RPUSH("key","value",...) - Pushes onto the head or tail of a list
+/// - LPOP("key") / RPOP("key") - Removes and returns the head or tail of a list
+/// - LLEN("key") - Returns the length of a list (0 if missing)
+/// - LRANGE("key","start","stop") - Lists elements between two inclusive indices (negative counts from the end)
+/// - HSET("key","field","value") - Sets a hash field, returns 1 if the field is new
+/// - HGET("key","field") - Returns a hash field, (nil) if missing
+/// - HGETALL("key") - Lists the fields and values of a hash
+/// - HDEL("key","field") - Deletes a hash field, returns 1 if it existed
+/// - SADD("key","member",...) - Adds members to a set, returns how many were new
+/// - SREM("key","member",...) - Removes members from a set, returns how many were removed
+/// - SMEMBERS("key") - Lists the members of a set
+/// - SISMEMBER("key","member") - Returns 1 if the member is in the set, 0 otherwise
+/// - TTL("key") - Returns remaining seconds, -1 without expiry, -2 if missing
+/// - KEYSTTL("pattern") - Lists `key ttl` pairs for matching keys, -1 without expiry
+/// - EXPIRENX("key","ttl") - Sets TTL only if the key has no expiry
+/// - EXPIREADD("key","ttl") - Extends the remaining TTL of a key
+/// - EXPIREALLAT("pattern",unix_seconds) - Sets an absolute expiry on all matching keys
+/// - RECENTKEYS(seconds) - Lists keys written within the last N seconds
+/// - PURGE - Removes expired keys from the database now
+/// - DBSIZE - Returns the number of live keys
+/// - RANDOMKEY - Returns a random live key, (nil) if there is none
+/// - SCAN(cursor,["pattern"],[count]) - Returns the next cursor and a batch of keys
+/// - FSYNC - Writes the database to disk and fsyncs before replying
+/// - SHARD("key",n) - Returns which of n shards a key maps to (no database needed)
+/// - QUOTA - Reports byte usage against the database quota
+/// - SETQUOTA(bytes) - Sets the database byte quota, 0 removes it (admin)
+/// - DEFAULTTTL - Reports the TTL given to keys set without one, in seconds
+/// - SETDEFAULTTTL("ttl") - Sets the default TTL, "" removes it (admin)
+/// - BIGKEYS(n) - Lists the n keys with the largest values (admin)
+/// - MAPVALUES("pattern","upper|lower|trim") - Transforms values of matching keys (admin)
+///
+/// When `length_prefixed` is set, value replies use the length-prefixed format.
+pub fn parse_statement(
+    input: &str,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    length_prefixed: bool,
+) -> Reply {
+    // Wait for any EXEC running on the database, and keep it out meanwhile
+    let _guard = current_db_instance.as_ref().map(|db| db.statement_guard());
+    run_statement(input, current_db_instance, length_prefixed)
+}
+
+/// Stores raw bytes sent with `SET key <len>` under `key`, without a TTL.
+/// Returns the reply for the client.
+pub fn set_bytes(key: &str, bytes: Vec<u8>, current_db_instance: &Option<Arc<DbInstance>>) -> Reply {
+    let Some(db_instance) = current_db_instance else {
+        return Reply::error("No database selected");
+    };
+
+    let _guard = db_instance.statement_guard();
+    let entry = ValueWithExpiry::new(Value::from_bytes(bytes), db_instance.default_ttl());
+    if let Err(e) = db_instance.check_memory(key, entry.value.size()) {
+        return Reply::error(e);
+    }
+    {
+        let mut db = db_instance.data.write(&[key]);
+        if let Err(e) = db_instance.check_quota(&db, key, entry.value.size()) {
+            return Reply::error(e);
+        }
+        db.insert(key.to_string(), entry);
+    }
+    db_instance.persist();
+    Reply::ok()
+}
+
+/// Returns the bytes of a string or binary value between two inclusive
+/// offsets for `GETRANGE key start end`, as GETRANGE does but without
+/// requiring the range to be text. A missing key gives no bytes.
+pub fn get_range_bytes(
+    key: &str,
+    start: &str,
+    end: &str,
+    current_db_instance: &Option<Arc<DbInstance>>,
+) -> Result<Vec<u8>, Reply> {
+    let (start, end) = range_offsets(start, end)?;
+    let Some(db_instance) = current_db_instance else {
+        return Err(Reply::error("No database selected"));
+    };
+
+    let _guard = db_instance.statement_guard();
+    let db = db_instance.data.read(&[key]);
+    match db.get(key) {
+        Some(val) if !val.is_expired() => match val.value.as_bytes() {
+            Some(bytes) => Ok(byte_range(bytes, start, end).to_vec()),
+            None => Err(Reply::error(WRONG_TYPE)),
+        },
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Returns the raw contents of a string or binary value for `GET key`,
+/// `None` for a missing or expired key, or an error message.
+pub fn get_bytes(key: &str, current_db_instance: &Option<Arc<DbInstance>>) -> Result<Option<Vec<u8>>, Reply> {
+    let Some(db_instance) = current_db_instance else {
+        return Err(Reply::error("No database selected"));
+    };
+
+    let _guard = db_instance.statement_guard();
+    let db = db_instance.data.read(&[key]);
+    match db.get(key) {
+        Some(val) if !val.is_expired() => match val.value.as_bytes() {
+            Some(bytes) => Ok(Some(bytes.to_vec())),
+            None => Err(Reply::error(WRONG_TYPE)),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Runs the statements queued by MULTI in order and returns their replies.
+/// No statement from another connection runs on the database in between.
+pub fn exec_transaction(
+    statements: &[String],
+    current_db_instance: &Option<Arc<DbInstance>>,
+    length_prefixed: bool,
+) -> Vec<Reply> {
+    let _guard = current_db_instance.as_ref().map(|db| db.transaction_guard());
+    statements
+        .iter()
+        .map(|statement| run_statement(statement, current_db_instance, length_prefixed))
+        .collect()
+}
+
+/// Executes a single statement. Callers hold the database's statement or
+/// transaction guard.
+fn run_statement(
+    input: &str,
+    current_db_instance: &Option<Arc<DbInstance>>,
+    length_prefixed: bool,
+) -> Reply {
     let input = input.trim();
 
     // Handle SET command
     if input.starts_with("SET(") && input.ends_with(')') {
         let content = &input[4..input.len() - 1];  // Extract content between parentheses
-        let args: Vec<&str> = content
-            .split(',')
-            .map(|s| s.trim().trim_matches('"'))  // Clean each argument
-            .collect();
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
         if args.len() < 2 {
-            return "Usage: SET(\"key\",\"value\",[\"5s|5m|5d\"])".to_string();
+            return Reply::error("Usage: SET(\"key\",\"value\",[\"5s|5m|5d\"|\"\"])");
         }
 
         let key = args[0].to_string();
         let value = args[1].to_string();
-        let mut ttl: Option<Duration> = None;
 
-        // Parse TTL if provided
-        if args.len() == 3 {
-            ttl = match parse_duration(args[2]) {
+        // Parse TTL if provided; an empty one means the key never expires,
+        // and without one the database's default TTL applies
+        let ttl: Option<Duration> = match args.get(2) {
+            Some(&"") => None,
+            Some(arg) => match parse_duration(arg) {
                 Ok(dur) => Some(dur),
-                Err(e) => return e,  // Return error message if TTL parsing fails
-            };
-        }
+                Err(e) => return Reply::error(e),  // Return error message if TTL parsing fails
+            },
+            None => current_db_instance.as_ref().and_then(|db| db.default_ttl()),
+        };
 
         let entry = ValueWithExpiry::new(value, ttl);
 
         match current_db_instance {
             Some(db_instance) => {
+                if let Err(e) = db_instance.check_memory(&key, entry.value.size()) {
+                    return Reply::error(e);
+                }
                 {
-                    let mut db = db_instance.data.lock().unwrap();
+                    let mut db = db_instance.data.write(&[&key]);
+                    if let Err(e) = db_instance.check_quota(&db, &key, entry.value.size()) {
+                        return Reply::error(e);
+                    }
                     db.insert(key, entry);
                 }
                 
                 // Persist after releasing the lock
                 db_instance.persist();
-                "OK".to_string()
+                Reply::ok()
             }
-            None => "No database selected".to_string(),
+            None => Reply::error("No database selected"),
         }
     } 
+    // Handle SETMETA command
+    else if input.starts_with("SETMETA(") && input.ends_with(')') {
+        let content = &input[8..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 3 {
+            return Reply::error("Usage: SETMETA(\"key\",\"value\",\"5s|5m|5d\"|\"\",[\"tag\"...])");
+        }
+
+        let key = args[0].to_string();
+        let value = args[1].to_string();
+        // An empty TTL argument means the key never expires
+        let ttl = if args[2].is_empty() {
+            None
+        } else {
+            match parse_duration(args[2]) {
+                Ok(dur) => Some(dur),
+                Err(e) => return Reply::error(e),
+            }
+        };
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in &args[3..] {
+            if tag.is_empty() {
+                return Reply::error("Error: Tags cannot be empty");
+            }
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+
+        let mut entry = ValueWithExpiry::new(value, ttl);
+        entry.tags = tags;
+
+        match current_db_instance {
+            Some(db_instance) => {
+                if let Err(e) = db_instance.check_memory(&key, entry.value.size()) {
+                    return Reply::error(e);
+                }
+                {
+                    let mut db = db_instance.data.write(&[&key]);
+                    if let Err(e) = db_instance.check_quota(&db, &key, entry.value.size()) {
+                        return Reply::error(e);
+                    }
+                    db.insert(key, entry);
+                }
+                db_instance.persist();
+                Reply::ok()
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SETNX command
+    else if input.starts_with("SETNX(") && input.ends_with(')') {
+        let content = &input[6..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 || args.len() > 3 {
+            return Reply::error("Usage: SETNX(\"key\",\"value\",[\"5s|5m|5d\"|\"\"])");
+        }
+
+        let key = args[0];
+        // Same TTL rules as SET
+        let ttl: Option<Duration> = match args.get(2) {
+            Some(&"") => None,
+            Some(arg) => match parse_duration(arg) {
+                Ok(dur) => Some(dur),
+                Err(e) => return Reply::error(e),
+            },
+            None => current_db_instance.as_ref().and_then(|db| db.default_ttl()),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                if let Err(e) = db_instance.check_memory(key, args[1].len()) {
+                    return Reply::error(e);
+                }
+                {
+                    // Check and insert under the same lock so only one caller wins
+                    let mut db = db_instance.data.write(&[key]);
+                    if db.get(key).is_some_and(|v| !v.is_expired()) {
+                        return Reply::Integer(0);
+                    }
+                    if let Err(e) = db_instance.check_quota(&db, key, args[1].len()) {
+                        return Reply::error(e);
+                    }
+                    db.insert(key.to_string(), ValueWithExpiry::new(args[1].to_string(), ttl));
+                }
+
+                db_instance.persist();
+                Reply::Integer(1)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle MSET command
+    else if input.starts_with("MSET(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return Reply::error("Usage: MSET(\"key\",\"value\",[\"key\",\"value\"...])");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                for pair in args.chunks(2) {
+                    if let Err(e) = db_instance.check_memory(pair[0], pair[1].len()) {
+                        return Reply::error(e);
+                    }
+                }
+                {
+                    let keys: Vec<&str> = args.iter().step_by(2).copied().collect();
+                    let mut db = db_instance.data.write(&keys);
+                    let mut previous: Vec<(&str, Option<ValueWithExpiry>)> = Vec::new();
+                    for pair in args.chunks(2) {
+                        if let Err(e) = db_instance.check_quota(&db, pair[0], pair[1].len()) {
+                            // Restore overwritten entries so a rejected batch changes nothing
+                            for (key, old) in previous.into_iter().rev() {
+                                match old {
+                                    Some(old) => db.insert(key.to_string(), old),
+                                    None => db.remove(key),
+                                };
+                            }
+                            return Reply::error(e);
+                        }
+                        let old = db.insert(
+                            pair[0].to_string(),
+                            ValueWithExpiry::new(pair[1].to_string(), db_instance.default_ttl()),
+                        );
+                        previous.push((pair[0], old));
+                    }
+                }
+
+                // A single rewrite for the whole batch
+                db_instance.persist();
+                Reply::ok()
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle MSETNX command
+    else if input.starts_with("MSETNX(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 || !args.len().is_multiple_of(2) {
+            return Reply::error("Usage: MSETNX(\"key\",\"value\",[\"key\",\"value\"...])");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                for pair in args.chunks(2) {
+                    if let Err(e) = db_instance.check_memory(pair[0], pair[1].len()) {
+                        return Reply::error(e);
+                    }
+                }
+                {
+                    let keys: Vec<&str> = args.iter().step_by(2).copied().collect();
+                    let mut db = db_instance.data.write(&keys);
+
+                    // All-or-nothing: any live key cancels the whole batch
+                    let any_exists = args
+                        .chunks(2)
+                        .any(|pair| db.get(pair[0]).is_some_and(|v| !v.is_expired()));
+                    if any_exists {
+                        return Reply::Integer(0);
+                    }
+
+                    let mut inserted: Vec<&str> = Vec::new();
+                    for pair in args.chunks(2) {
+                        if let Err(e) = db_instance.check_quota(&db, pair[0], pair[1].len()) {
+                            // Roll back so a rejected batch leaves no partial writes
+                            for key in inserted {
+                                db.remove(key);
+                            }
+                            return Reply::error(e);
+                        }
+                        db.insert(
+                            pair[0].to_string(),
+                            ValueWithExpiry::new(pair[1].to_string(), db_instance.default_ttl()),
+                        );
+                        inserted.push(pair[0]);
+                    }
+                }
+
+                db_instance.persist();
+                Reply::Integer(1)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle TAGS command
+    else if input.starts_with("TAGS(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[key]);
+                match db.get(key) {
+                    Some(val) if !val.is_expired() => Reply::bulks(val.tags.clone()),
+                    _ => Reply::error(format!("Error: Key \"{}\" not found", key)),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle KEYSBYTAG and TAGCOUNT commands
+    else if (input.starts_with("KEYSBYTAG(") || input.starts_with("TAGCOUNT(")) && input.ends_with(')') {
+        let count_only = input.starts_with("TAGCOUNT(");
+        let start = if count_only { 9 } else { 10 };
+        let content = &input[start..input.len() - 1];
+        let tag = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // A linear scan keeps SET/DEL free of index bookkeeping
+                let db = db_instance.data.read_all();
+                let mut keys: Vec<String> = db
+                    .iter()
+                    .filter(|(_, v)| !v.is_expired() && v.tags.iter().any(|t| t == tag))
+                    .map(|(k, _)| k.clone())
+                    .collect();
+
+                if count_only {
+                    Reply::Integer(keys.len() as i64)
+                } else {
+                    keys.sort();
+                    Reply::bulks(keys)
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
     // Handle GET command
     else if input.starts_with("GET(") && input.ends_with(')') {
         let content = &input[4..input.len() - 1];
-        let key = content.trim().trim_matches('"');
+        let key = &unquote(content);
 
         match current_db_instance {
             Some(db_instance) => {
-                let mut db = db_instance.data.lock().unwrap();
+                let db = db_instance.data.read(&[key]);
                 match db.get(key) {
-                    Some(val) if !val.is_expired() => val.value.clone(),
-                    Some(_) => {
-                        db.remove(key);
-                        drop(db); 
-                        db_instance.persist();
-                        format!("Error: Key \"{}\" has expired and is deleted", key)
-                    }
-                    None => format!("Error: Key \"{}\" not found", key),
+                    Some(val) if !val.is_expired() => match val.value.as_str() {
+                        Some(value) => Reply::bulk(value),
+                        None => Reply::error(WRONG_TYPE),
+                    },
+                    // Expired keys are left for the cleaner so reads never need the write lock
+                    Some(_) if length_prefixed => Reply::Bulk(None),
+                    Some(_) => Reply::error(format!("Error: Key \"{}\" has expired", key)),
+                    None if length_prefixed => Reply::Bulk(None),
+                    None => Reply::error(format!("Error: Key \"{}\" not found", key)),
                 }
             }
-            None => "No database selected".to_string(),
+            None => Reply::error("No database selected"),
         }
     } 
-    // Handle DEL command
-    else if input.starts_with("DEL(") && input.ends_with(')') {
-        let content = &input[4..input.len() - 1];
-        let key = content.trim().trim_matches('"');
+    // Handle APPEND command
+    else if input.starts_with("APPEND(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: APPEND(\"key\",\"suffix\")");
+        }
+
+        let (key, suffix) = (args[0], args[1]);
 
         match current_db_instance {
             Some(db_instance) => {
-                let removed = {
-                    let mut db = db_instance.data.lock().unwrap();
-                    db.remove(key).is_some()
+                let new_len = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let current = match db.get(key) {
+                        Some(val) if !val.is_expired() => match val.value.as_str() {
+                            Some(value) => Some(value.to_string()),
+                            None => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+                    let appended = format!("{}{}", current.as_deref().unwrap_or(""), suffix);
+                    if let Err(e) = db_instance.check_quota(&db, key, appended.len()) {
+                        return Reply::error(e);
+                    }
+
+                    let new_len = appended.len();
+                    match db.get_mut(key) {
+                        // Append in place so the key keeps its TTL
+                        Some(val) if current.is_some() => val.value = appended.into(),
+                        _ => {
+                            db.insert(key.to_string(), ValueWithExpiry::new(appended, db_instance.default_ttl()));
+                        }
+                    }
+                    new_len
                 };
-                
-                // Persist only if key was actually removed
-                if removed {
-                    db_instance.persist();
-                    "OK".to_string()
-                } else {
-                    format!("Error: Key \"{}\" not found", key)
-                }
+
+                db_instance.persist();
+                Reply::Integer(new_len as i64)
             }
-            None => "No database selected".to_string(),
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SETRANGE command
+    else if input.starts_with("SETRANGE(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 3 {
+            return Reply::error("Usage: SETRANGE(\"key\",\"offset\",\"value\")");
+        }
+
+        let (key, patch) = (args[0], args[2].as_bytes());
+        let offset = match args[1].parse::<usize>() {
+            Ok(offset) => offset,
+            Err(_) => return Reply::error("Error: offset must be a non-negative integer"),
+        };
+        if offset.saturating_add(patch.len()) > MAX_SETRANGE_LEN {
+            return Reply::error(format!("Error: SETRANGE would make the value longer than {} bytes", MAX_SETRANGE_LEN));
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let new_len = {
+                    let mut db = db_instance.data.write(&[key]);
+                    // The current contents, and whether they are text
+                    let current = match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::String(s) => Some((s.as_bytes().to_vec(), true)),
+                            Value::Bytes(bytes) => Some((bytes.clone(), false)),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+                    let text = current.as_ref().is_none_or(|(_, text)| *text);
+                    // Pad with zero bytes up to the offset, then overwrite
+                    let mut bytes = current.as_ref().map(|(bytes, _)| bytes.clone()).unwrap_or_default();
+                    let end = offset + patch.len();
+                    if bytes.len() < end {
+                        bytes.resize(end, 0);
+                    }
+                    bytes[offset..end].copy_from_slice(patch);
+                    // Text stays text, rather than quietly becoming a bytes value
+                    if text && std::str::from_utf8(&bytes).is_err() {
+                        return Reply::error(SPLIT_CHARACTER);
+                    }
+                    if let Err(e) = db_instance.check_quota(&db, key, bytes.len()) {
+                        return Reply::error(e);
+                    }
+
+                    let new_len = bytes.len();
+                    match db.get_mut(key) {
+                        // Patch in place so the key keeps its TTL
+                        Some(val) if current.is_some() => val.value = Value::from_bytes(bytes),
+                        _ => {
+                            let entry = ValueWithExpiry::new(Value::from_bytes(bytes), db_instance.default_ttl());
+                            db.insert(key.to_string(), entry);
+                        }
+                    }
+                    new_len
+                };
+
+                db_instance.persist();
+                Reply::Integer(new_len as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle INCR and DECR commands
+    else if (input.starts_with("INCR(") || input.starts_with("DECR(")) && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let key = &unquote(content);
+        let delta = if input.starts_with("INCR(") { 1 } else { -1 };
+
+        match current_db_instance {
+            Some(db_instance) => increment_by(db_instance, key, delta),
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle INCRBY and DECRBY commands
+    else if (input.starts_with("INCRBY(") || input.starts_with("DECRBY(")) && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: INCRBY(\"key\",\"n\") or DECRBY(\"key\",\"n\")");
+        }
+
+        let delta = match args[1].parse::<i64>() {
+            Ok(n) if input.starts_with("INCRBY(") => n,
+            Ok(n) => match n.checked_neg() {
+                Some(n) => n,
+                None => return Reply::error("Error: increment or decrement would overflow"),
+            },
+            Err(_) => return Reply::error("Error: increment is not an integer"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => increment_by(db_instance, args[0], delta),
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle INCRBYFLOAT command
+    else if input.starts_with("INCRBYFLOAT(") && input.ends_with(')') {
+        let content = &input[12..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: INCRBYFLOAT(\"key\",\"n\")");
+        }
+
+        let key = args[0];
+        let delta = match args[1].parse::<f64>() {
+            Ok(n) if n.is_finite() => n,
+            _ => return Reply::error("Error: increment is not a valid float"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let updated = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let current = match db.get(key) {
+                        Some(val) if !val.is_expired() => match val.value.as_str().map(str::parse::<f64>) {
+                            Some(Ok(n)) if n.is_finite() => Some(n),
+                            Some(_) => return Reply::error("Error: value is not a valid float"),
+                            None => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+
+                    let updated = current.unwrap_or(0.0) + delta;
+                    if !updated.is_finite() {
+                        return Reply::error("Error: increment would produce NaN or Infinity");
+                    }
+                    // Display gives the shortest form that round-trips, e.g. 3 rather than 3.0
+                    let updated = updated.to_string();
+                    if let Err(e) = db_instance.check_quota(&db, key, updated.len()) {
+                        return Reply::error(e);
+                    }
+                    match db.get_mut(key) {
+                        // Update in place so the key keeps its TTL
+                        Some(val) if current.is_some() => val.value = updated.clone().into(),
+                        _ => {
+                            db.insert(key.to_string(), ValueWithExpiry::new(updated.clone(), db_instance.default_ttl()));
+                        }
+                    }
+                    updated
+                };
+
+                db_instance.persist();
+                Reply::bulk(updated)
+            }
+            None => Reply::error("No database selected"),
         }
-    } else {
-        "Unknown command".to_string()  // Fallback for invalid commands
     }
-}
\ No newline at end of file
+    // Handle GETSET command
+    else if input.starts_with("GETSET(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: GETSET(\"key\",\"value\")");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let old = {
+                    let mut db = db_instance.data.write(&[args[0]]);
+                    if db
+                        .get(args[0])
+                        .is_some_and(|v| !v.is_expired() && v.value.as_str().is_none())
+                    {
+                        return Reply::error(WRONG_TYPE);
+                    }
+                    if let Err(e) = db_instance.check_quota(&db, args[0], args[1].len()) {
+                        return Reply::error(e);
+                    }
+                    // Like SET without a TTL, the replacement gets the default TTL
+                    // rather than the one the old value had
+                    db.insert(
+                        args[0].to_string(),
+                        ValueWithExpiry::new(args[1].to_string(), db_instance.default_ttl()),
+                    )
+                    .filter(|v| !v.is_expired())
+                    .and_then(|v| v.value.into_string())
+                };
+
+                db_instance.persist();
+                Reply::Bulk(old)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle GETDEL command
+    else if input.starts_with("GETDEL(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let removed = {
+                    let mut db = db_instance.data.write(&[key]);
+                    // Leave values of other kinds in place
+                    if db
+                        .get(key)
+                        .is_some_and(|v| !v.is_expired() && v.value.as_str().is_none())
+                    {
+                        return Reply::error(WRONG_TYPE);
+                    }
+                    db.remove(key)
+                };
+
+                match removed {
+                    Some(val) => {
+                        db_instance.persist();
+                        let expired = val.is_expired();
+                        let value = val.value.into_string().unwrap_or_default();
+                        match (expired, length_prefixed) {
+                            (false, _) => Reply::bulk(value),
+                            (true, true) => Reply::Bulk(None),
+                            (true, false) => Reply::error(format!("Error: Key \"{}\" not found", key)),
+                        }
+                    }
+                    None if length_prefixed => Reply::Bulk(None),
+                    None => Reply::error(format!("Error: Key \"{}\" not found", key)),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle GETORSET command
+    else if input.starts_with("GETORSET(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 || args.len() > 3 {
+            return Reply::error("Usage: GETORSET(\"key\",\"default\",[\"5s|5m|5d\"])");
+        }
+
+        let key = args[0];
+        // Same TTL rules as SET
+        let ttl: Option<Duration> = match args.get(2) {
+            Some(&"") => None,
+            Some(arg) => match parse_duration(arg) {
+                Ok(dur) => Some(dur),
+                Err(e) => return Reply::error(e),
+            },
+            None => current_db_instance.as_ref().and_then(|db| db.default_ttl()),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // Check and fill under one lock so concurrent callers agree on the value
+                let (value, inserted) = {
+                    let mut db = db_instance.data.write(&[key]);
+                    match db.get(key) {
+                        Some(val) if !val.is_expired() => match val.value.as_str() {
+                            Some(value) => (value.to_string(), false),
+                            None => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => {
+                            let default = args[1].to_string();
+                            if let Err(e) = db_instance.check_quota(&db, key, default.len()) {
+                                return Reply::error(e);
+                            }
+                            db.insert(key.to_string(), ValueWithExpiry::new(default.clone(), ttl));
+                            (default, true)
+                        }
+                    }
+                };
+
+                if inserted {
+                    db_instance.persist();
+                }
+                Reply::bulk(value)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle GETRESET command
+    else if input.starts_with("GETRESET(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let result = {
+                    let mut db = db_instance.data.write(&[key]);
+                    match db.get_mut(key) {
+                        Some(val) if !val.is_expired() => match val.value.as_str().map(str::parse::<i64>) {
+                            Some(Ok(old)) => {
+                                // Reset in place so the counter keeps its TTL
+                                val.value = "0".to_string().into();
+                                Ok(Some(old))
+                            }
+                            Some(Err(_)) => Err("Error: value is not an integer".to_string()),
+                            None => Err(WRONG_TYPE.to_string()),
+                        },
+                        // A missing counter reads as 0 and is left absent
+                        _ => Ok(None),
+                    }
+                };
+
+                match result {
+                    Ok(Some(old)) => {
+                        db_instance.persist();
+                        Reply::Integer(old)
+                    }
+                    Ok(None) => Reply::Integer(0),
+                    Err(e) => Reply::error(e),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle MGET command
+    else if input.starts_with("MGET(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let keys = split_args(content);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // Read every key under one lock for a consistent view
+                let values: Vec<Option<String>> = {
+                    let db = db_instance.data.read(&keys);
+                    keys.iter()
+                        .map(|key| match db.get(key) {
+                            // Values of other kinds read as missing
+                            Some(val) if !val.is_expired() => val.value.as_str().map(str::to_string),
+                            _ => None,
+                        })
+                        .collect()
+                };
+
+                Reply::Array(values.into_iter().map(Reply::Bulk).collect())
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle TOUCH command
+    else if input.starts_with("TOUCH(") && input.ends_with(')') {
+        let content = &input[6..input.len() - 1];
+        let keys = split_args(content);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let (live, reaped) = {
+                    let mut db = db_instance.data.write(&keys);
+                    let mut live = 0;
+                    let mut reaped = false;
+                    // A key given twice is counted twice
+                    for key in &keys {
+                        match db.get(key) {
+                            Some(val) if val.is_expired() => {
+                                db.remove(key);
+                                reaped = true;
+                            }
+                            Some(_) => live += 1,
+                            None => {}
+                        }
+                    }
+                    (live, reaped)
+                };
+
+                if reaped {
+                    db_instance.persist();
+                }
+                Reply::Integer(live as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle STRLEN command
+    else if input.starts_with("STRLEN(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[key]);
+                match db.get(key) {
+                    Some(val) if !val.is_expired() => match val.value.as_str() {
+                        Some(value) => Reply::Integer(value.len() as i64),
+                        None => Reply::error(WRONG_TYPE),
+                    },
+                    _ => Reply::Integer(0),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle GETRANGE command
+    else if input.starts_with("GETRANGE(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 3 {
+            return Reply::error("Usage: GETRANGE(\"key\",\"start\",\"end\")");
+        }
+
+        let (start, end) = match range_offsets(args[1], args[2]) {
+            Ok(offsets) => offsets,
+            Err(e) => return e,
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let value = {
+                    let db = db_instance.data.read(&[args[0]]);
+                    match db.get(args[0]) {
+                        Some(val) if !val.is_expired() => match val.value.as_str() {
+                            // Replies are text, so the range must be too
+                            Some(value) => match std::str::from_utf8(byte_range(value.as_bytes(), start, end)) {
+                                Ok(range) => range.to_string(),
+                                Err(_) => {
+                                    return Reply::error(format!(
+                                        "{}, use GETRANGE <key> <start> <end> for raw bytes",
+                                        SPLIT_CHARACTER
+                                    ));
+                                }
+                            },
+                            None => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => String::new(),
+                    }
+                };
+                Reply::bulk(value)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle TYPE command
+    else if input.starts_with("TYPE(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[key]);
+                match db.get(key) {
+                    Some(val) if !val.is_expired() => Reply::status(val.value.type_name()),
+                    _ => Reply::status("none"),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle DEL command
+    else if input.starts_with("DEL(") && input.ends_with(')') {
+        let content = &input[4..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let removed = {
+                    let mut db = db_instance.data.write(&[key]);
+                    db.remove(key).is_some()
+                };
+                
+                // Persist only if key was actually removed
+                if removed {
+                    db_instance.persist();
+                    Reply::ok()
+                } else {
+                    Reply::error(format!("Error: Key \"{}\" not found", key))
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle RENAME command
+    else if input.starts_with("RENAME(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: RENAME(\"oldkey\",\"newkey\")");
+        }
+
+        let (old_key, new_key) = (args[0], args[1]);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                {
+                    // Remove and insert under one lock so readers never see both or neither
+                    let mut db = db_instance.data.write(&[old_key, new_key]);
+                    let entry = match db.remove(old_key) {
+                        Some(val) if !val.is_expired() => val,
+                        _ => return Reply::error(format!("Error: Key \"{}\" not found", old_key)),
+                    };
+                    if let Err(e) = db_instance.check_quota(&db, new_key, entry.value.size()) {
+                        db.insert(old_key.to_string(), entry);
+                        return Reply::error(e);
+                    }
+                    db.insert(new_key.to_string(), entry);
+                }
+
+                db_instance.persist();
+                Reply::ok()
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle COPY command
+    else if input.starts_with("COPY(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if !(2..=3).contains(&args.len()) || args.get(2).is_some_and(|arg| *arg != "replace") {
+            return Reply::error("Usage: COPY(\"src\",\"dst\",[\"replace\"])");
+        }
+
+        let (src, dst) = (args[0], args[1]);
+        let replace = args.len() == 3;
+
+        match current_db_instance {
+            Some(db_instance) => {
+                {
+                    // Read src and write dst under one lock so the copy is consistent
+                    let mut db = db_instance.data.write(&[src, dst]);
+                    let mut copy = match db.get(src) {
+                        Some(val) if !val.is_expired() => val.clone(),
+                        _ => return Reply::Integer(0),
+                    };
+                    if !replace && db.get(dst).is_some_and(|val| !val.is_expired()) {
+                        return Reply::Integer(0);
+                    }
+                    if let Err(e) = db_instance.check_quota(&db, dst, copy.value.size()) {
+                        return Reply::error(e);
+                    }
+                    // The copy is a new write; the deadline and tags carry over
+                    copy.created_at = SystemTime::now();
+                    db.insert(dst.to_string(), copy);
+                }
+
+                db_instance.persist();
+                Reply::Integer(1)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle LPUSH and RPUSH commands
+    else if (input.starts_with("LPUSH(") || input.starts_with("RPUSH(")) && input.ends_with(')') {
+        let content = &input[6..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 {
+            return Reply::error("Usage: LPUSH(\"key\",\"value\",...) or RPUSH(\"key\",\"value\",...)");
+        }
+
+        let (key, values) = (args[0], &args[1..]);
+        let front = input.starts_with("LPUSH(");
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let new_len = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let current_size = match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::List(_) => Some(val.value.size()),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+                    let added: usize = values.iter().map(|v| v.len()).sum();
+                    if let Err(e) = db_instance.check_quota(&db, key, current_size.unwrap_or(0) + added) {
+                        return Reply::error(e);
+                    }
+
+                    if current_size.is_none() {
+                        let entry = ValueWithExpiry::new(Value::List(VecDeque::new()), db_instance.default_ttl());
+                        db.insert(key.to_string(), entry);
+                    }
+                    // Push in place so an existing list keeps its TTL
+                    let Some(Value::List(list)) = db.get_mut(key).map(|val| &mut val.value) else {
+                        unreachable!("key holds a list");
+                    };
+                    for value in values {
+                        if front {
+                            list.push_front(value.to_string());
+                        } else {
+                            list.push_back(value.to_string());
+                        }
+                    }
+                    list.len()
+                };
+
+                db_instance.persist();
+                Reply::Integer(new_len as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle LPOP and RPOP commands
+    else if (input.starts_with("LPOP(") || input.starts_with("RPOP(")) && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let key = &unquote(content);
+        let front = input.starts_with("LPOP(");
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let popped = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let popped = match db.get_mut(key) {
+                        Some(val) if !val.is_expired() => match &mut val.value {
+                            Value::List(list) if front => list.pop_front(),
+                            Value::List(list) => list.pop_back(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+                    // A list is removed once its last element is popped
+                    if db.get(key).is_some_and(|v| matches!(&v.value, Value::List(list) if list.is_empty())) {
+                        db.remove(key);
+                    }
+                    popped
+                };
+
+                if popped.is_some() {
+                    db_instance.persist();
+                }
+                Reply::Bulk(popped)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle LLEN command
+    else if input.starts_with("LLEN(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[key]);
+                match db.get(key) {
+                    Some(val) if !val.is_expired() => match &val.value {
+                        Value::List(list) => Reply::Integer(list.len() as i64),
+                        _ => Reply::error(WRONG_TYPE),
+                    },
+                    _ => Reply::Integer(0),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle LRANGE command
+    else if input.starts_with("LRANGE(") && input.ends_with(')') {
+        let content = &input[7..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 3 {
+            return Reply::error("Usage: LRANGE(\"key\",\"start\",\"stop\")");
+        }
+
+        let (start, stop) = match (args[1].parse::<i64>(), args[2].parse::<i64>()) {
+            (Ok(start), Ok(stop)) => (start, stop),
+            _ => return Reply::error("Error: start and stop must be integers"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let items: Vec<String> = {
+                    let db = db_instance.data.read(&[args[0]]);
+                    match db.get(args[0]) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::List(list) => {
+                                // Negative indices count from the end; both ends are inclusive
+                                let len = list.len() as i64;
+                                let start = if start < 0 { (len + start).max(0) } else { start };
+                                let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+                                if start > stop {
+                                    Vec::new()
+                                } else {
+                                    list.range(start as usize..=stop as usize).cloned().collect()
+                                }
+                            }
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => Vec::new(),
+                    }
+                };
+                Reply::bulks(items)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle HSET command
+    else if input.starts_with("HSET(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 3 {
+            return Reply::error("Usage: HSET(\"key\",\"field\",\"value\")");
+        }
+
+        let (key, field, value) = (args[0], args[1], args[2]);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let added = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let new_size = match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::Hash(hash) => {
+                                let replaced = hash
+                                    .get(field)
+                                    .map(|v| field.len() + v.len())
+                                    .unwrap_or(0);
+                                Some(val.value.size() - replaced)
+                            }
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+                    let size = new_size.unwrap_or(0) + field.len() + value.len();
+                    if let Err(e) = db_instance.check_quota(&db, key, size) {
+                        return Reply::error(e);
+                    }
+
+                    if new_size.is_none() {
+                        let entry = ValueWithExpiry::new(Value::Hash(HashMap::new()), db_instance.default_ttl());
+                        db.insert(key.to_string(), entry);
+                    }
+                    // Set the field in place so an existing hash keeps its TTL
+                    let Some(Value::Hash(hash)) = db.get_mut(key).map(|val| &mut val.value) else {
+                        unreachable!("key holds a hash");
+                    };
+                    hash.insert(field.to_string(), value.to_string()).is_none()
+                };
+
+                db_instance.persist();
+                if added { Reply::Integer(1) } else { Reply::Integer(0) }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle HGET command
+    else if input.starts_with("HGET(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: HGET(\"key\",\"field\")");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let value = {
+                    let db = db_instance.data.read(&[args[0]]);
+                    match db.get(args[0]) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::Hash(hash) => hash.get(args[1]).cloned(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    }
+                };
+
+                Reply::Bulk(value)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle HGETALL command
+    else if input.starts_with("HGETALL(") && input.ends_with(')') {
+        let content = &input[8..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let mut fields: Vec<(String, String)> = {
+                    let db = db_instance.data.read(&[key]);
+                    match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::Hash(hash) => hash
+                                .iter()
+                                .map(|(f, v)| (f.clone(), v.clone()))
+                                .collect(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => Vec::new(),
+                    }
+                };
+                fields.sort();
+
+                // Fields and values alternate, ordered by field name
+                Reply::bulks(fields.into_iter().flat_map(|(field, value)| [field, value]))
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle HDEL command
+    else if input.starts_with("HDEL(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: HDEL(\"key\",\"field\")");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let removed = {
+                    let mut db = db_instance.data.write(&[args[0]]);
+                    let removed = match db.get_mut(args[0]) {
+                        Some(val) if !val.is_expired() => match &mut val.value {
+                            Value::Hash(hash) => hash.remove(args[1]).is_some(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => false,
+                    };
+                    // A hash is removed along with its last field
+                    let emptied = db
+                        .get(args[0])
+                        .is_some_and(|v| matches!(&v.value, Value::Hash(hash) if hash.is_empty()));
+                    if emptied {
+                        db.remove(args[0]);
+                    }
+                    removed
+                };
+
+                if removed {
+                    db_instance.persist();
+                    Reply::Integer(1)
+                } else {
+                    Reply::Integer(0)
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SADD command
+    else if input.starts_with("SADD(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 {
+            return Reply::error("Usage: SADD(\"key\",\"member\",...)");
+        }
+
+        let (key, members) = (args[0], &args[1..]);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let added = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let current = match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::Set(set) => Some((set, val.value.size())),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => None,
+                    };
+
+                    // Only members not already in the set count towards the quota
+                    let mut new_members: Vec<&str> = Vec::new();
+                    for member in members {
+                        let present = current.is_some_and(|(set, _)| set.contains(*member));
+                        if !present && !new_members.contains(member) {
+                            new_members.push(member);
+                        }
+                    }
+                    let current_size = current.map(|(_, size)| size).unwrap_or(0);
+                    let added_size: usize = new_members.iter().map(|m| m.len()).sum();
+                    let exists = current.is_some();
+                    if let Err(e) = db_instance.check_quota(&db, key, current_size + added_size) {
+                        return Reply::error(e);
+                    }
+
+                    if !exists {
+                        let entry = ValueWithExpiry::new(Value::Set(HashSet::new()), db_instance.default_ttl());
+                        db.insert(key.to_string(), entry);
+                    }
+                    // Add in place so an existing set keeps its TTL
+                    let Some(Value::Set(set)) = db.get_mut(key).map(|val| &mut val.value) else {
+                        unreachable!("key holds a set");
+                    };
+                    for member in &new_members {
+                        set.insert(member.to_string());
+                    }
+                    new_members.len()
+                };
+
+                if added > 0 {
+                    db_instance.persist();
+                }
+                Reply::Integer(added as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SREM command
+    else if input.starts_with("SREM(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() < 2 {
+            return Reply::error("Usage: SREM(\"key\",\"member\",...)");
+        }
+
+        let (key, members) = (args[0], &args[1..]);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let removed = {
+                    let mut db = db_instance.data.write(&[key]);
+                    let removed = match db.get_mut(key) {
+                        Some(val) if !val.is_expired() => match &mut val.value {
+                            Value::Set(set) => members.iter().filter(|m| set.remove(**m)).count(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => 0,
+                    };
+                    // A set is removed along with its last member
+                    let emptied = db
+                        .get(key)
+                        .is_some_and(|v| matches!(&v.value, Value::Set(set) if set.is_empty()));
+                    if emptied {
+                        db.remove(key);
+                    }
+                    removed
+                };
+
+                if removed > 0 {
+                    db_instance.persist();
+                }
+                Reply::Integer(removed as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SMEMBERS command
+    else if input.starts_with("SMEMBERS(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let mut members: Vec<String> = {
+                    let db = db_instance.data.read(&[key]);
+                    match db.get(key) {
+                        Some(val) if !val.is_expired() => match &val.value {
+                            Value::Set(set) => set.iter().cloned().collect(),
+                            _ => return Reply::error(WRONG_TYPE),
+                        },
+                        _ => Vec::new(),
+                    }
+                };
+                members.sort();
+                Reply::bulks(members)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SISMEMBER command
+    else if input.starts_with("SISMEMBER(") && input.ends_with(')') {
+        let content = &input[10..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: SISMEMBER(\"key\",\"member\")");
+        }
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[args[0]]);
+                match db.get(args[0]) {
+                    Some(val) if !val.is_expired() => match &val.value {
+                        Value::Set(set) if set.contains(args[1]) => Reply::Integer(1),
+                        Value::Set(_) => Reply::Integer(0),
+                        _ => Reply::error(WRONG_TYPE),
+                    },
+                    _ => Reply::Integer(0),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle TTL command
+    else if input.starts_with("TTL(") && input.ends_with(')') {
+        let content = &input[4..input.len() - 1];
+        let key = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let db = db_instance.data.read(&[key]);
+                match db.get(key) {
+                    Some(val) if !val.is_expired() => match val.ttl() {
+                        Some(remaining) => Reply::Integer(remaining.as_secs() as i64),
+                        None => Reply::Integer(-1),
+                    },
+                    _ => Reply::Integer(-2),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle KEYSTTL command
+    else if input.starts_with("KEYSTTL(") && input.ends_with(')') {
+        let content = &input[8..input.len() - 1];
+        let pattern = &unquote(content);
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // One lock over every shard, so all TTLs are read at the same moment
+                let db = db_instance.data.read_all();
+                let mut entries: Vec<(String, i64)> = db
+                    .iter()
+                    .filter(|(k, v)| !v.is_expired() && glob_match(pattern, k))
+                    .map(|(k, v)| (k.clone(), v.ttl().map_or(-1, |remaining| remaining.as_secs() as i64)))
+                    .collect();
+                entries.sort();
+
+                let lines: Vec<String> = entries
+                    .into_iter()
+                    .map(|(key, ttl)| format!("{} {}", key, ttl))
+                    .collect();
+                Reply::bulks(lines)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle EXPIRENX command
+    else if input.starts_with("EXPIRENX(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: EXPIRENX(\"key\",\"5s|5m|5d\")");
+        }
+
+        let key = args[0];
+        let ttl = match parse_duration(args[1]) {
+            // Checked before the lock is taken, so turning it into a deadline can't overflow
+            Ok(dur) if dur > MAX_TTL => return Reply::error("Error: TTL too long (at most 365 days)"),
+            Ok(dur) => dur,
+            Err(e) => return Reply::error(e),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let updated = {
+                    let mut db = db_instance.data.write(&[key]);
+                    match db.get_mut(key) {
+                        // Only keys that exist and never expire get a TTL
+                        Some(val) if !val.is_expired() && val.expires_at().is_none() => {
+                            val.set_ttl(Some(ttl));
+                            true
+                        }
+                        _ => false,
+                    }
+                };
+
+                if updated {
+                    db_instance.persist();
+                    Reply::Integer(1)
+                } else {
+                    Reply::Integer(0)
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle EXPIREADD command
+    else if input.starts_with("EXPIREADD(") && input.ends_with(')') {
+        let content = &input[10..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: EXPIREADD(\"key\",\"5s|5m|5d\")");
+        }
+
+        let key = args[0];
+        let delta = match parse_duration(args[1]) {
+            Ok(dur) => dur,
+            Err(e) => return Reply::error(e),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let result = {
+                    let mut db = db_instance.data.write(&[key]);
+                    match db.get_mut(key) {
+                        Some(val) if !val.is_expired() => match val.ttl() {
+                            Some(remaining) => {
+                                // Grow the remaining lifetime, capped at MAX_TTL
+                                let extended = remaining.saturating_add(delta).min(MAX_TTL);
+                                val.set_ttl(Some(extended));
+                                Ok(extended.as_secs())
+                            }
+                            // Keys without a TTL are never made volatile implicitly
+                            None => Err(format!("Error: Key \"{}\" has no TTL", key)),
+                        },
+                        _ => Err(format!("Error: Key \"{}\" not found", key)),
+                    }
+                };
+
+                match result {
+                    Ok(secs) => {
+                        db_instance.persist();
+                        Reply::Integer(secs as i64)
+                    }
+                    Err(e) => Reply::error(e),
+                }
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle EXPIREALLAT command
+    else if input.starts_with("EXPIREALLAT(") && input.ends_with(')') {
+        let content = &input[12..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: EXPIREALLAT(\"pattern\",unix_seconds)");
+        }
+
+        let pattern = args[0];
+        let now = unix_millis() / 1000;
+        // Deadlines further away than MAX_TTL are rejected before any lock
+        // is taken, so turning them into times can't overflow
+        let (deadline, expires_at) = match args[1].parse::<u64>() {
+            Ok(secs) if secs <= now.saturating_add(MAX_TTL.as_secs()) => {
+                match UNIX_EPOCH.checked_add(Duration::from_secs(secs)) {
+                    Some(expires_at) => (secs, expires_at),
+                    None => return Reply::error("Error: Invalid unix timestamp"),
+                }
+            }
+            _ => return Reply::error("Error: Invalid unix timestamp"),
+        };
+        let already_passed = deadline <= now;
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let affected = {
+                    let mut db = db_instance.data.write_all();
+                    let matching: Vec<String> = db
+                        .iter()
+                        .filter(|(k, v)| !v.is_expired() && glob_match(pattern, k))
+                        .map(|(k, _)| k.clone())
+                        .collect();
+
+                    for key in &matching {
+                        if already_passed {
+                            // A deadline in the past expires the keys right away
+                            db.remove(key);
+                        } else if let Some(val) = db.get_mut(key) {
+                            val.set_deadline(Some(expires_at));
+                        }
+                    }
+                    matching.len()
+                };
+
+                if affected > 0 {
+                    db_instance.persist();
+                }
+                Reply::Integer(affected as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SCAN command
+    else if input.starts_with("SCAN(") && input.ends_with(')') {
+        let content = &input[5..input.len() - 1];
+        let args = split_args(content);
+        let usage = Reply::error(format!(
+            "Usage: SCAN(cursor,[\"pattern\"],[count]) with count between 1 and {}",
+            MAX_SCAN_COUNT
+        ));
+        if args.is_empty() || args.len() > 3 {
+            return usage;
+        }
+
+        let Ok(cursor) = args[0].parse::<u64>() else {
+            return usage;
+        };
+        let pattern = args.get(1).map(String::as_str).unwrap_or("*");
+        let count = match args.get(2).map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) if (1..=MAX_SCAN_COUNT).contains(&n) => n,
+            Some(_) => return usage,
+            None => DEFAULT_SCAN_COUNT,
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // The pattern filters a batch after it is taken, like in Redis
+                let (next, keys) = db_instance.scan(cursor, count);
+                let mut reply = vec![next.to_string()];
+                reply.extend(keys.into_iter().filter(|key| glob_match(pattern, key)));
+                Reply::bulks(reply)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle RECENTKEYS command
+    else if input.starts_with("RECENTKEYS(") && input.ends_with(')') {
+        let content = &input[11..input.len() - 1];
+        let secs = match unquote(content).parse::<u64>() {
+            Ok(secs) => secs,
+            Err(_) => return Reply::error("Usage: RECENTKEYS(seconds)"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                let window = Duration::from_secs(secs);
+                let db = db_instance.data.read_all();
+                let mut keys: Vec<String> = db
+                    .iter()
+                    .filter(|(_, v)| {
+                        !v.is_expired()
+                            && v.created_at.elapsed().map(|age| age <= window).unwrap_or(true)
+                    })
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                keys.sort();
+                Reply::bulks(keys)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle PURGE command
+    else if input == "PURGE" {
+        match current_db_instance {
+            Some(db_instance) => {
+                let removed = db_instance.remove_expired();
+                if !removed.is_empty() {
+                    db_instance.persist();
+                }
+                Reply::Integer(removed.len() as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle DBSIZE command
+    else if input == "DBSIZE" {
+        match current_db_instance {
+            // Skip expired keys so the count matches what GET would see
+            Some(db_instance) => Reply::Integer(db_instance.key_count() as i64),
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle RANDOMKEY command
+    else if input == "RANDOMKEY" {
+        match current_db_instance {
+            Some(db_instance) => Reply::Bulk(db_instance.random_key()),
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle FSYNC command
+    else if input == "FSYNC" {
+        match current_db_instance {
+            // Unlike persist(), failures are reported to the client. The fsync
+            // blocks, so let the runtime move other tasks off this thread
+            Some(db_instance) => match tokio::task::block_in_place(|| db_instance.sync_to_file()) {
+                Ok(()) => Reply::ok(),
+                Err(e) => Reply::error(format!("Error: Failed to sync database: {}", e)),
+            },
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SHARD command
+    else if input.starts_with("SHARD(") && input.ends_with(')') {
+        let content = &input[6..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: SHARD(\"key\",n)");
+        }
+
+        match args[1].parse::<u64>() {
+            Ok(n) if n > 0 => Reply::Integer((fnv1a_64(args[0]) % n) as i64),
+            _ => Reply::error("Error: Shard count must be a positive integer"),
+        }
+    }
+    // Handle QUOTA command
+    else if input == "QUOTA" {
+        match current_db_instance {
+            Some(db_instance) => {
+                let used = db_instance.memory_used();
+                let limit = db_instance
+                    .quota()
+                    .map(|l| l.to_string())
+                    .unwrap_or_else(|| "unlimited".to_string());
+                Reply::statuses([format!("used:{}", used), format!("limit:{}", limit)])
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SETQUOTA command
+    else if input.starts_with("SETQUOTA(") && input.ends_with(')') {
+        let content = &input[9..input.len() - 1];
+        let limit = match unquote(content).parse::<u64>() {
+            Ok(limit) => limit,
+            Err(_) => return Reply::error("Usage: SETQUOTA(bytes)"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                db_instance.set_quota(if limit == 0 { None } else { Some(limit) });
+                log_info_with(
+                    &format!("📏 Quota of '{}' set to {} bytes", db_instance.name, limit),
+                    &[("database", &db_instance.name), ("command", "SETQUOTA")],
+                );
+                db_instance.persist();
+                Reply::ok()
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle DEFAULTTTL command
+    else if input == "DEFAULTTTL" {
+        match current_db_instance {
+            Some(db_instance) => match db_instance.default_ttl() {
+                Some(ttl) => Reply::Integer(ttl.as_secs() as i64),
+                None => Reply::status("none"),
+            },
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle SETDEFAULTTTL command
+    else if input.starts_with("SETDEFAULTTTL(") && input.ends_with(')') {
+        let content = &input[14..input.len() - 1];
+        let ttl = match unquote(content).as_str() {
+            "" => None,
+            ttl => match parse_duration(ttl) {
+                Ok(dur) if !dur.is_zero() => Some(dur),
+                Ok(_) => return Reply::error("Error: Default TTL must be positive, use \"\" to remove it"),
+                Err(e) => return Reply::error(e),
+            },
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                db_instance.set_default_ttl(ttl);
+                log_info_with(
+                    &format!(
+                        "⏳ Default TTL of '{}' set to {}",
+                        db_instance.name,
+                        ttl.map_or("none".to_string(), |ttl| format!("{}s", ttl.as_secs()))
+                    ),
+                    &[("database", &db_instance.name), ("command", "SETDEFAULTTTL")],
+                );
+                db_instance.persist();
+                Reply::ok()
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle BIGKEYS command
+    else if input.starts_with("BIGKEYS(") && input.ends_with(')') {
+        let content = &input[8..input.len() - 1];
+        let n = match unquote(content).parse::<usize>() {
+            Ok(n) if (1..=MAX_BIGKEYS).contains(&n) => n,
+            _ => return Reply::error(format!("Usage: BIGKEYS(n) with n between 1 and {}", MAX_BIGKEYS)),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // Keep only the n largest in a min-heap instead of sorting every key
+                let mut largest: BinaryHeap<Reverse<(usize, String)>> = BinaryHeap::with_capacity(n + 1);
+                {
+                    let db = db_instance.data.read_all();
+                    for (key, val) in db.iter().filter(|(_, v)| !v.is_expired()) {
+                        largest.push(Reverse((val.value.size(), key.clone())));
+                        if largest.len() > n {
+                            largest.pop();
+                        }
+                    }
+                }
+
+                let entries: Vec<String> = largest
+                    .into_sorted_vec()
+                    .into_iter()
+                    .map(|Reverse((size, key))| format!("{} {}", key, size))
+                    .collect();
+                Reply::bulks(entries)
+            }
+            None => Reply::error("No database selected"),
+        }
+    }
+    // Handle MAPVALUES command
+    else if input.starts_with("MAPVALUES(") && input.ends_with(')') {
+        let content = &input[10..input.len() - 1];
+        let args = split_args(content);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        if args.len() != 2 {
+            return Reply::error("Usage: MAPVALUES(\"pattern\",\"upper|lower|trim\")");
+        }
+
+        let pattern = args[0];
+        let transform: fn(&str) -> String = match args[1] {
+            "upper" => str::to_uppercase,
+            "lower" => str::to_lowercase,
+            "trim" => |v| v.trim().to_string(),
+            _ => return Reply::error("Invalid transform (use upper, lower, or trim)"),
+        };
+
+        match current_db_instance {
+            Some(db_instance) => {
+                // Match and rewrite under one lock. Only the rewritten keys
+                // are changed through the guard, so only they are logged
+                let transformed = {
+                    let mut db = db_instance.data.write_all();
+                    let rewrites: Vec<(String, String)> = db
+                        .iter()
+                        .filter_map(|(key, val)| match &val.value {
+                            // Only string values are rewritten
+                            Value::String(value) if !val.is_expired() && glob_match(pattern, key) => {
+                                Some((key.clone(), transform(value)))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+
+                    let mut previous: Vec<(&str, String)> = Vec::new();
+                    for (key, value) in &rewrites {
+                        // Upper-casing can grow a value, e.g. "ß" to "SS"
+                        if let Err(e) = db_instance.check_quota(&db, key, value.len()) {
+                            // Restore rewritten values so a rejected batch changes nothing
+                            for (key, old) in previous.into_iter().rev() {
+                                if let Some(val) = db.get_mut(key) {
+                                    val.value = Value::String(old);
+                                }
+                            }
+                            return Reply::error(e);
+                        }
+                        if let Some(val) = db.get_mut(key)
+                            && let Value::String(current) = &mut val.value
+                        {
+                            previous.push((key, std::mem::replace(current, value.clone())));
+                        }
+                    }
+                    rewrites.len()
+                };
+
+                log_info_with(
+                    &format!(
+                        "🛠️ MAPVALUES '{}' ({}) rewrote {} keys in '{}'",
+                        pattern, args[1], transformed, db_instance.name
+                    ),
+                    &[("database", &db_instance.name), ("command", "MAPVALUES")],
+                );
+                if transformed > 0 {
+                    db_instance.persist();
+                }
+                Reply::Integer(transformed as i64)
+            }
+            None => Reply::error("No database selected"),
+        }
+    } else {
+        Reply::error(unknown_command(input))  // Fallback for invalid commands
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_instance;
+
+    fn run(input: &str, db: &Option<Arc<DbInstance>>) -> Reply {
+        parse_statement(input, db, false)
+    }
+
+    #[test]
+    fn values_that_look_like_statuses_or_errors_are_values() {
+        let db = Some(Arc::new(test_instance()));
+        for value in ["42", "OK", "(nil)", "Error: boom", "Usage: nothing", "-1"] {
+            let set = format!("SET(\"k\",\"{}\")", value);
+            assert_eq!(run(&set, &db), Reply::ok());
+            assert_eq!(run("GET(\"k\")", &db), Reply::bulk(value));
+            assert_eq!(run("GETSET(\"k\",\"x\")", &db), Reply::bulk(value));
+            assert_eq!(run(&format!("RPUSH(\"l\",\"{}\")", value), &db), Reply::Integer(1));
+            assert_eq!(run("LPOP(\"l\")", &db), Reply::bulk(value));
+        }
+        assert_eq!(
+            run("MGET(\"k\",\"missing\")", &db),
+            Reply::Array(vec![Reply::bulk("x"), Reply::Bulk(None)])
+        );
+    }
+
+    #[test]
+    fn counts_are_integers_and_failures_are_errors() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("INCRBY(\"n\",\"5\")", &db), Reply::Integer(5));
+        assert_eq!(run("STRLEN(\"n\")", &db), Reply::Integer(1));
+        assert_eq!(run("TTL(\"n\")", &db), Reply::Integer(-1));
+        assert_eq!(run("TYPE(\"n\")", &db), Reply::status("string"));
+        assert_eq!(run("GET(\"missing\")", &db), Reply::error("Error: Key \"missing\" not found"));
+        assert_eq!(parse_statement("GET(\"missing\")", &db, true), Reply::Bulk(None));
+        assert!(matches!(run("SET(\"k\")", &db), Reply::Error(_)));
+        assert!(matches!(run("NOPE(\"k\")", &db), Reply::Error(_)));
+        assert_eq!(run("GET(\"k\")", &None), Reply::error("No database selected"));
+    }
+
+    #[test]
+    fn writes_over_the_quota_are_rejected() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("SETQUOTA(10)", &db), Reply::ok());
+        assert_eq!(run("SET(\"k\",\"123456789\")", &db), Reply::ok());
+        assert_eq!(
+            run("SET(\"j\",\"x\")", &db),
+            Reply::error("Error: Quota exceeded (12 of 10 bytes)")
+        );
+        assert_eq!(run("GET(\"j\")", &db), Reply::error("Error: Key \"j\" not found"));
+        // Replacing a value only counts the difference
+        assert_eq!(run("SET(\"k\",\"x\")", &db), Reply::ok());
+        assert_eq!(
+            run("QUOTA", &db),
+            Reply::statuses(["used:2".to_string(), "limit:10".to_string()])
+        );
+        assert!(matches!(run("MSET(\"a\",\"1234\",\"b\",\"1234\")", &db), Reply::Error(_)));
+        assert_eq!(run("GET(\"a\")", &db), Reply::error("Error: Key \"a\" not found"));
+    }
+
+    #[test]
+    fn counters_growing_past_the_quota_are_rejected() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("SETQUOTA(3)", &db), Reply::ok());
+        assert_eq!(run("INCRBY(\"n\",\"10\")", &db), Reply::Integer(10));
+        assert!(matches!(run("INCRBY(\"n\",\"90\")", &db), Reply::Error(e) if e.contains("Quota")));
+        assert!(matches!(run("INCRBYFLOAT(\"n\",\"0.5\")", &db), Reply::Error(e) if e.contains("Quota")));
+        assert_eq!(run("GET(\"n\")", &db), Reply::bulk("10"));
+    }
+
+    #[test]
+    fn expirenx_only_sets_a_ttl_on_keys_without_one() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"plain\",\"v\")", &db);
+        run("SET(\"volatile\",\"v\",\"1m\")", &db);
+        assert_eq!(run("EXPIRENX(\"plain\",\"1d\")", &db), Reply::Integer(1));
+        assert!(matches!(run("TTL(\"plain\")", &db), Reply::Integer(86399..=86400)));
+        assert_eq!(run("EXPIRENX(\"volatile\",\"1d\")", &db), Reply::Integer(0));
+        assert!(matches!(run("TTL(\"volatile\")", &db), Reply::Integer(59..=60)));
+        assert_eq!(run("EXPIRENX(\"missing\",\"1d\")", &db), Reply::Integer(0));
+        run("SET(\"forever\",\"v\")", &db);
+        let too_long = Reply::error("Error: TTL too long (at most 365 days)");
+        assert_eq!(run("EXPIRENX(\"forever\",\"18446744073709551615s\")", &db), too_long);
+        assert_eq!(run("EXPIRENX(\"forever\",\"366d\")", &db), too_long);
+        assert_eq!(run("TTL(\"forever\")", &db), Reply::Integer(-1));
+        assert_eq!(run("EXPIRENX(\"forever\",\"365d\")", &db), Reply::Integer(1));
+    }
+
+    #[test]
+    fn expireadd_extends_the_remaining_ttl() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"volatile\",\"v\",\"1m\")", &db);
+        run("SET(\"plain\",\"v\")", &db);
+        assert!(matches!(run("EXPIREADD(\"volatile\",\"1m\")", &db), Reply::Integer(119..=120)));
+        assert!(matches!(run("TTL(\"volatile\")", &db), Reply::Integer(119..=120)));
+        assert_eq!(run("EXPIREADD(\"plain\",\"1m\")", &db), Reply::error("Error: Key \"plain\" has no TTL"));
+        assert_eq!(run("TTL(\"plain\")", &db), Reply::Integer(-1));
+        assert_eq!(run("EXPIREADD(\"missing\",\"1m\")", &db), Reply::error("Error: Key \"missing\" not found"));
+        // A huge delta is capped instead of overflowing
+        let max_ttl = MAX_TTL.as_secs() as i64;
+        assert_eq!(run("EXPIREADD(\"volatile\",\"18446744073709551615s\")", &db), Reply::Integer(max_ttl));
+        assert!(matches!(run("TTL(\"volatile\")", &db), Reply::Integer(t) if t >= max_ttl - 1));
+    }
+
+    #[test]
+    fn recentkeys_lists_keys_written_within_the_window() {
+        let instance = Arc::new(test_instance());
+        let db = Some(instance.clone());
+        run("SET(\"old\",\"v\")", &db);
+        run("SET(\"new\",\"v\")", &db);
+        // As if "old" had been written two minutes ago
+        if let Some(val) = instance.data.write(&["old"]).get_mut("old") {
+            val.created_at -= Duration::from_secs(120);
+        }
+        let keys = |names: &[&str]| Reply::bulks(names.iter().map(|name| name.to_string()));
+
+        assert_eq!(run("RECENTKEYS(60)", &db), keys(&["new"]));
+        assert_eq!(run("RECENTKEYS(300)", &db), keys(&["new", "old"]));
+        // Changing the TTL keeps the timestamp, overwriting resets it
+        run("EXPIRENX(\"old\",\"1d\")", &db);
+        assert_eq!(run("RECENTKEYS(60)", &db), keys(&["new"]));
+        run("SET(\"old\",\"w\")", &db);
+        assert_eq!(run("RECENTKEYS(60)", &db), keys(&["new", "old"]));
+        assert_eq!(run("RECENTKEYS(soon)", &db), Reply::error("Usage: RECENTKEYS(seconds)"));
+    }
+
+    #[test]
+    fn setmeta_stores_the_value_ttl_and_tags() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("SETMETA(\"k\",\"v\",\"1m\",\"red\",\"blue\",\"red\")", &db), Reply::ok());
+        assert_eq!(run("GET(\"k\")", &db), Reply::bulk("v"));
+        assert!(matches!(run("TTL(\"k\")", &db), Reply::Integer(59..=60)));
+        // Duplicate tags are stored once
+        assert_eq!(run("TAGS(\"k\")", &db), Reply::bulks(["red".to_string(), "blue".to_string()]));
+
+        assert_eq!(run("SETMETA(\"k\",\"w\",\"\")", &db), Reply::ok());
+        assert_eq!(run("TTL(\"k\")", &db), Reply::Integer(-1));
+        assert_eq!(run("TAGS(\"k\")", &db), Reply::Array(Vec::new()));
+        assert_eq!(run("SETMETA(\"k\",\"v\",\"\",\"\")", &db), Reply::error("Error: Tags cannot be empty"));
+    }
+
+    #[test]
+    fn keysbytag_lists_live_keys_carrying_a_tag() {
+        let db = Some(Arc::new(test_instance()));
+        run("SETMETA(\"b\",\"v\",\"\",\"red\")", &db);
+        run("SETMETA(\"a\",\"v\",\"\",\"red\",\"blue\")", &db);
+        run("SETMETA(\"c\",\"v\",\"\",\"blue\")", &db);
+        run("SET(\"d\",\"v\")", &db);
+
+        assert_eq!(run("KEYSBYTAG(\"red\")", &db), Reply::bulks(["a".to_string(), "b".to_string()]));
+        assert_eq!(run("TAGCOUNT(\"blue\")", &db), Reply::Integer(2));
+        // A plain SET replaces the key along with its tags
+        run("SET(\"a\",\"w\")", &db);
+        assert_eq!(run("KEYSBYTAG(\"red\")", &db), Reply::bulks(["b".to_string()]));
+        assert_eq!(run("KEYSBYTAG(\"green\")", &db), Reply::Array(Vec::new()));
+    }
+
+    #[test]
+    fn getreset_never_loses_concurrent_increments() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("GETRESET(\"hits\")", &db), Reply::Integer(0));
+        assert_eq!(run("TYPE(\"hits\")", &db), Reply::status("none"));
+
+        let incrementers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        run("INCR(\"hits\")", &db);
+                    }
+                })
+            })
+            .collect();
+        // Collect while the counters are being incremented
+        let mut collected = 0;
+        while !incrementers.iter().all(|handle| handle.is_finished()) {
+            if let Reply::Integer(n) = run("GETRESET(\"hits\")", &db) {
+                collected += n;
+            }
+        }
+        for handle in incrementers {
+            handle.join().unwrap();
+        }
+        if let Reply::Integer(n) = run("GETRESET(\"hits\")", &db) {
+            collected += n;
+        }
+        assert_eq!(collected, 2000);
+        assert_eq!(run("GET(\"hits\")", &db), Reply::bulk("0"));
+
+        run("SET(\"name\",\"x\")", &db);
+        assert_eq!(run("GETRESET(\"name\")", &db), Reply::error("Error: value is not an integer"));
+    }
+
+    #[test]
+    fn expireallat_sets_one_deadline_for_matching_keys() {
+        let db = Some(Arc::new(test_instance()));
+        for key in ["user:1", "user:2", "other"] {
+            run(&format!("SET(\"{}\",\"v\")", key), &db);
+        }
+        let deadline = unix_millis() / 1000 + 3600;
+        assert_eq!(run(&format!("EXPIREALLAT(\"user:*\",{})", deadline), &db), Reply::Integer(2));
+        assert!(matches!(run("TTL(\"user:1\")", &db), Reply::Integer(3598..=3600)));
+        assert!(matches!(run("TTL(\"user:2\")", &db), Reply::Integer(3598..=3600)));
+        assert_eq!(run("TTL(\"other\")", &db), Reply::Integer(-1));
+
+        // A deadline that has passed expires the keys at once
+        assert_eq!(run("EXPIREALLAT(\"user:*\",1)", &db), Reply::Integer(2));
+        assert_eq!(run("DBSIZE", &db), Reply::Integer(1));
+        assert_eq!(run("EXPIREALLAT(\"*\",soon)", &db), Reply::error("Error: Invalid unix timestamp"));
+        // Deadlines beyond the longest TTL are rejected instead of overflowing
+        let too_far = unix_millis() / 1000 + MAX_TTL.as_secs() + 60;
+        assert_eq!(run(&format!("EXPIREALLAT(\"*\",{})", too_far), &db), Reply::error("Error: Invalid unix timestamp"));
+        assert_eq!(run("EXPIREALLAT(\"*\",18446744073709551615)", &db), Reply::error("Error: Invalid unix timestamp"));
+        assert_eq!(run("TTL(\"other\")", &db), Reply::Integer(-1));
+    }
+
+    #[test]
+    fn shard_maps_keys_with_fnv1a_without_a_database() {
+        // FNV-1a 64 of "a" is 0xaf63dc4c8601ec8c, so clients can reproduce it
+        assert_eq!(fnv1a_64("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(run("SHARD(\"a\",16)", &None), Reply::Integer(12));
+        assert_eq!(run("SHARD(\"a\",10)", &None), Reply::Integer(6));
+        assert_eq!(run("SHARD(\"a\",1)", &None), Reply::Integer(0));
+        for n in ["0", "-1", "x"] {
+            assert_eq!(
+                run(&format!("SHARD(\"a\",{})", n), &None),
+                Reply::error("Error: Shard count must be a positive integer")
+            );
+        }
+    }
+
+    #[test]
+    fn getorset_stores_the_default_only_once() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(run("GETORSET(\"k\",\"first\",\"1m\")", &db), Reply::bulk("first"));
+        assert!(matches!(run("TTL(\"k\")", &db), Reply::Integer(59..=60)));
+        assert_eq!(run("GETORSET(\"k\",\"second\")", &db), Reply::bulk("first"));
+
+        // Racing callers all see the value stored by the winner
+        let callers: Vec<_> = (0..8)
+            .map(|i| {
+                let db = db.clone();
+                std::thread::spawn(move || run(&format!("GETORSET(\"race\",\"{}\")", i), &db))
+            })
+            .collect();
+        let replies: Vec<Reply> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+        assert!(replies.iter().all(|reply| *reply == run("GET(\"race\")", &db)));
+
+        run("RPUSH(\"l\",\"a\")", &db);
+        assert_eq!(run("GETORSET(\"l\",\"x\")", &db), Reply::error(WRONG_TYPE));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fsync_writes_the_database_before_replying() {
+        let instance = Arc::new(test_instance());
+        let db = Some(instance.clone());
+        run("SET(\"k\",\"v\")", &db);
+        assert_eq!(run("FSYNC", &db), Reply::ok());
+        let path = crate::db::db_path(&instance.name);
+        assert!(std::fs::read_to_string(&path).unwrap().contains("\"k\""));
+
+        // Failures are reported rather than retried later
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::create_dir_all(&tmp_path).unwrap();
+        assert!(matches!(run("FSYNC", &db), Reply::Error(e) if e.starts_with("Error: Failed to sync database")));
+        std::fs::remove_dir(&tmp_path).unwrap();
+    }
+
+    #[test]
+    fn bigkeys_lists_the_largest_values_first() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"small\",\"x\")", &db);
+        run("SET(\"big\",\"0123456789\")", &db);
+        run("RPUSH(\"list\",\"abc\",\"def\")", &db);
+        run("HSET(\"hash\",\"f\",\"v\")", &db);
+
+        assert_eq!(run("BIGKEYS(2)", &db), Reply::bulks(["big 10".to_string(), "list 6".to_string()]));
+        let all = ["big 10", "list 6", "hash 2", "small 1"].map(String::from);
+        assert_eq!(run("BIGKEYS(10)", &db), Reply::bulks(all));
+        assert!(matches!(run("BIGKEYS(0)", &db), Reply::Error(e) if e.starts_with("Usage: BIGKEYS(n)")));
+    }
+
+    #[test]
+    fn msetnx_sets_all_pairs_or_none() {
+        let instance = Arc::new(test_instance());
+        let db = Some(instance.clone());
+        assert_eq!(run("MSETNX(\"a\",\"1\",\"b\",\"2\")", &db), Reply::Integer(1));
+        assert_eq!(run("MGET(\"a\",\"b\")", &db), Reply::bulks(["1".to_string(), "2".to_string()]));
+
+        // One existing key leaves every key as it was
+        assert_eq!(run("MSETNX(\"c\",\"3\",\"a\",\"x\")", &db), Reply::Integer(0));
+        assert_eq!(run("GET(\"a\")", &db), Reply::bulk("1"));
+        assert_eq!(run("TYPE(\"c\")", &db), Reply::status("none"));
+
+        // Expired keys count as absent, even before they are removed
+        run("SET(\"gone\",\"v\")", &db);
+        if let Some(val) = instance.data.write(&["gone"]).get_mut("gone") {
+            val.set_deadline(Some(UNIX_EPOCH + Duration::from_secs(1)));
+        }
+        assert_eq!(run("MSETNX(\"gone\",\"back\",\"c\",\"3\")", &db), Reply::Integer(1));
+        assert_eq!(run("GET(\"gone\")", &db), Reply::bulk("back"));
+        assert!(matches!(run("MSETNX(\"a\",\"1\",\"b\")", &db), Reply::Error(_)));
+    }
+
+    #[test]
+    fn purge_removes_expired_keys_of_the_current_database_only() {
+        let (first, second) = (Arc::new(test_instance()), Arc::new(test_instance()));
+        for instance in [&first, &second] {
+            let db = Some(instance.clone());
+            run("SET(\"live\",\"v\")", &db);
+            run("SET(\"gone\",\"v\")", &db);
+            if let Some(val) = instance.data.write(&["gone"]).get_mut("gone") {
+                val.set_deadline(Some(UNIX_EPOCH + Duration::from_secs(1)));
+            }
+        }
+
+        let db = Some(first.clone());
+        assert_eq!(run("PURGE", &db), Reply::Integer(1));
+        assert_eq!(first.data.read_all().len(), 1);
+        assert_eq!(run("PURGE", &db), Reply::Integer(0));
+        assert_eq!(second.data.read_all().len(), 2);
+        assert_eq!(run("PURGE", &None), Reply::error("No database selected"));
+    }
+
+    #[test]
+    fn commas_inside_quotes_belong_to_the_value() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(split_args(r#""a,b", "c" ,d"#), ["a,b", "c", "d"]);
+        assert_eq!(run(r#"SET("csv","1,2,3")"#, &db), Reply::ok());
+        assert_eq!(run(r#"GET("csv")"#, &db), Reply::bulk("1,2,3"));
+        assert_eq!(run(r#"SET("k,ey","v","1m")"#, &db), Reply::ok());
+        assert!(matches!(run(r#"TTL("k,ey")"#, &db), Reply::Integer(59..=60)));
+        assert_eq!(run(r#"MSET("a","x,y","b","")"#, &db), Reply::ok());
+        assert_eq!(run(r#"MGET("a","b")"#, &db), Reply::bulks(["x,y".to_string(), String::new()]));
+        // Semicolons inside quotes don't split statements either
+        assert_eq!(split_statements(r#"SET("k","a;b"); GET("k")"#), [r#"SET("k","a;b")"#, r#"GET("k")"#]);
+    }
+
+    #[test]
+    fn escaped_quotes_and_backslashes_round_trip() {
+        let db = Some(Arc::new(test_instance()));
+        assert_eq!(unquote(r#""say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(unquote(r#""a\\b""#), r"a\b");
+        // Other escapes are kept as they are
+        assert_eq!(unquote(r#""a\nb""#), r"a\nb");
+        assert_eq!(split_args(r#""a\",b","c""#), [r#"a",b"#, "c"]);
+
+        for value in [r#"say "hi", then go"#, r"C:\path\", r#"\""#] {
+            assert_eq!(unquote(&quote(value)), value);
+            assert_eq!(run(&format!("SET(\"k\",{})", quote(value)), &db), Reply::ok());
+            assert_eq!(run("GET(\"k\")", &db), Reply::bulk(value));
+        }
+    }
+
+    #[test]
+    fn commands_creating_keys_apply_the_default_ttl() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"kept\",\"v\",\"\")", &db);
+        assert_eq!(run("SETDEFAULTTTL(\"1m\")", &db), Reply::ok());
+
+        for (statement, key) in [
+            ("APPEND(\"append\",\"x\")", "append"),
+            ("SETRANGE(\"range\",\"2\",\"x\")", "range"),
+            ("INCR(\"incr\")", "incr"),
+            ("INCRBY(\"incrby\",\"5\")", "incrby"),
+            ("INCRBYFLOAT(\"float\",\"0.5\")", "float"),
+            ("GETORSET(\"getorset\",\"x\")", "getorset"),
+            ("GETSET(\"getset\",\"x\")", "getset"),
+            ("LPUSH(\"lpush\",\"x\")", "lpush"),
+            ("RPUSH(\"rpush\",\"x\")", "rpush"),
+            ("HSET(\"hset\",\"f\",\"x\")", "hset"),
+            ("SADD(\"sadd\",\"x\")", "sadd"),
+        ] {
+            assert!(!matches!(run(statement, &db), Reply::Error(_)), "{} failed", statement);
+            let ttl = run(&format!("TTL(\"{}\")", key), &db);
+            assert!(matches!(ttl, Reply::Integer(59..=60)), "{} left TTL {:?}", statement, ttl);
+        }
+
+        // Changing an existing key keeps its expiry, an explicit "" means none
+        assert_eq!(run("APPEND(\"kept\",\"x\")", &db), Reply::Integer(2));
+        assert_eq!(run("TTL(\"kept\")", &db), Reply::Integer(-1));
+        run("GETORSET(\"forever\",\"x\",\"\")", &db);
+        assert_eq!(run("TTL(\"forever\")", &db), Reply::Integer(-1));
+    }
+
+    #[test]
+    fn getrange_never_splits_characters_of_text() {
+        let db = Some(Arc::new(test_instance()));
+        // "é" is bytes 1 and 2
+        run("SET(\"k\",\"héllo\")", &db);
+        assert_eq!(run("GETRANGE(\"k\",\"0\",\"2\")", &db), Reply::bulk("hé"));
+        assert!(matches!(run("GETRANGE(\"k\",\"0\",\"1\")", &db), Reply::Error(e) if e.starts_with(SPLIT_CHARACTER)));
+        // The raw form returns exactly the bytes asked for
+        assert_eq!(get_range_bytes("k", "0", "1", &db), Ok(vec![b'h', 0xc3]));
+        assert_eq!(get_range_bytes("k", "-3", "-1", &db), Ok(b"llo".to_vec()));
+        assert_eq!(get_range_bytes("missing", "0", "1", &db), Ok(Vec::new()));
+        assert_eq!(get_range_bytes("k", "a", "1", &db), Err(Reply::error("Error: start and end must be integers")));
+    }
+
+    #[test]
+    fn setrange_never_splits_characters_of_text() {
+        let instance = Arc::new(test_instance());
+        let db = Some(instance.clone());
+        // "é" is bytes 1 and 2
+        run("SET(\"k\",\"héllo\")", &db);
+        assert_eq!(run("SETRANGE(\"k\",\"2\",\"x\")", &db), Reply::error(SPLIT_CHARACTER));
+        assert_eq!(run("GET(\"k\")", &db), Reply::bulk("héllo"));
+        assert_eq!(run("SETRANGE(\"k\",\"1\",\"e\")", &db), Reply::error(SPLIT_CHARACTER));
+        assert_eq!(run("SETRANGE(\"k\",\"1\",\"ee\")", &db), Reply::Integer(6));
+        assert_eq!(run("GET(\"k\")", &db), Reply::bulk("heello"));
+
+        // Binary values can be patched anywhere
+        assert_eq!(set_bytes("bin", vec![0xff, 0xfe, 0xfd], &db), Reply::ok());
+        assert_eq!(run("SETRANGE(\"bin\",\"1\",\"é\")", &db), Reply::Integer(3));
+        assert_eq!(get_bytes("bin", &db), Ok(Some(vec![0xff, 0xc3, 0xa9])));
+        assert_eq!(instance.data.read(&["bin"]).get("bin").unwrap().value.type_name(), "bytes");
+    }
+
+    #[test]
+    fn mapvalues_rewrites_only_matching_strings() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"user:1\",\"  Ann \")", &db);
+        run("SET(\"user:2\",\"bob\")", &db);
+        run("SET(\"other\",\"carl\")", &db);
+        run("RPUSH(\"user:list\",\"dan\")", &db);
+        assert_eq!(run("MAPVALUES(\"user:*\",\"upper\")", &db), Reply::Integer(2));
+        assert_eq!(run("GET(\"user:1\")", &db), Reply::bulk("  ANN "));
+        assert_eq!(run("GET(\"user:2\")", &db), Reply::bulk("BOB"));
+        assert_eq!(run("GET(\"other\")", &db), Reply::bulk("carl"));
+        assert_eq!(run("LPOP(\"user:list\")", &db), Reply::bulk("dan"));
+        assert_eq!(run("MAPVALUES(\"user:1\",\"trim\")", &db), Reply::Integer(1));
+        assert_eq!(run("GET(\"user:1\")", &db), Reply::bulk("ANN"));
+        assert_eq!(run("MAPVALUES(\"nothing:*\",\"lower\")", &db), Reply::Integer(0));
+        assert!(matches!(run("MAPVALUES(\"user:*\",\"reverse\")", &db), Reply::Error(_)));
+    }
+
+    #[test]
+    fn mapvalues_growing_past_the_quota_changes_nothing() {
+        let db = Some(Arc::new(test_instance()));
+        run("SET(\"a\",\"x\")", &db);
+        // "ŉ" is 2 bytes and upper-cases to the 3 bytes of "ʼN"
+        run("SET(\"b\",\"ŉŉ\")", &db);
+        assert_eq!(run("SETQUOTA(8)", &db), Reply::ok());
+        assert_eq!(
+            run("MAPVALUES(\"*\",\"upper\")", &db),
+            Reply::error("Error: Quota exceeded (9 of 8 bytes)")
+        );
+        assert_eq!(run("GET(\"a\")", &db), Reply::bulk("x"));
+        assert_eq!(run("GET(\"b\")", &db), Reply::bulk("ŉŉ"));
+        assert_eq!(run("MAPVALUES(\"a\",\"upper\")", &db), Reply::Integer(1));
+        assert_eq!(run("GET(\"a\")", &db), Reply::bulk("X"));
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Messages buffered per subscriber; slower subscribers miss messages
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Channel the cleaner announces expired keys on, as `<database> <key>`.
+/// Clients can subscribe to it but not publish to it.
+pub const EXPIRED_CHANNEL: &str = "expired";
+
+/// Named broadcast channels shared by every connection. A channel exists
+/// while it has subscribers and is created by the first one.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: Mutex<HashMap<String, broadcast::Sender<String>>>,
+}
+
+// Type alias for the pub/sub handle passed to each connection.
+pub type SharedPubSub = Arc<PubSub>;
+
+impl PubSub {
+    /// Returns a receiver for every message published on `channel` from now on.
+    pub fn subscribe(&self, channel: &str) -> broadcast::Receiver<String> {
+        let mut channels = self.channels.lock().unwrap();
+        match channels.get(channel) {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+                channels.insert(channel.to_string(), sender);
+                receiver
+            }
+        }
+    }
+
+    /// Sends `message` to every subscriber of `channel` and returns how
+    /// many there were. Channels whose subscribers have all left are removed.
+    pub fn publish(&self, channel: &str, message: String) -> usize {
+        let mut channels = self.channels.lock().unwrap();
+        let Some(sender) = channels.get(channel) else {
+            return 0;
+        };
+        match sender.send(message) {
+            Ok(receivers) => receivers,
+            Err(_) => {
+                channels.remove(channel);
+                0
+            }
+        }
+    }
+}
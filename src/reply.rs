@@ -0,0 +1,229 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// The reply to a command. Statements and session commands say what kind
+/// of reply they give, and `ReplyWriter` formats it for the session's
+/// protocol options, so a stored value is never mistaken for a status or
+/// an error whatever its text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    // A short fixed reply such as `OK` or a type name
+    Status(String),
+    // A failure, with its whole message, e.g. `Error: value is not an integer`
+    Error(String),
+    // A count, length or other number
+    Integer(i64),
+    // A value or key read from a database, or nil when there is none
+    Bulk(Option<String>),
+    // A list of replies
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply::Status("OK".to_string())
+    }
+
+    pub fn status(status: impl Into<String>) -> Self {
+        Reply::Status(status.into())
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    pub fn bulk(value: impl Into<String>) -> Self {
+        Reply::Bulk(Some(value.into()))
+    }
+
+    /// An array of values, e.g. keys or list items.
+    pub fn bulks(values: impl IntoIterator<Item = String>) -> Self {
+        Reply::Array(values.into_iter().map(|value| Reply::Bulk(Some(value))).collect())
+    }
+
+    /// An array of statuses, e.g. command names or `field:value` lines.
+    pub fn statuses(lines: impl IntoIterator<Item = String>) -> Self {
+        Reply::Array(lines.into_iter().map(Reply::Status).collect())
+    }
+
+    /// Formats the reply without a trailing line ending.
+    ///
+    /// Plain replies are the bare text, with `(nil)` for a missing value and
+    /// arrays as a `*<count>` line followed by one item per line. With
+    /// `length_prefixed`, values are `$<len>\r\n<value>` and nil is `$-1`.
+    /// With `typed`, every reply is prefixed with its type, loosely following
+    /// RESP: `+` for a status, `-ERR` for an error (without the `Error: `
+    /// prefix), `:` for an integer and `$` for a value, with `$-1` for nil.
+    pub fn format(&self, typed: bool, length_prefixed: bool) -> String {
+        match self {
+            Reply::Status(status) if typed => format!("+{}", status),
+            Reply::Status(status) => status.clone(),
+            Reply::Error(message) if typed => {
+                format!("-ERR {}", message.strip_prefix("Error: ").unwrap_or(message))
+            }
+            Reply::Error(message) => message.clone(),
+            Reply::Integer(n) if typed => format!(":{}", n),
+            Reply::Integer(n) => n.to_string(),
+            Reply::Bulk(Some(value)) if length_prefixed => format!("${}\r\n{}", value.len(), value),
+            Reply::Bulk(Some(value)) if typed => format!("${}", value),
+            Reply::Bulk(Some(value)) => value.clone(),
+            Reply::Bulk(None) if typed || length_prefixed => "$-1".to_string(),
+            Reply::Bulk(None) => "(nil)".to_string(),
+            Reply::Array(items) => {
+                let mut reply = format!("*{}", items.len());
+                for item in items {
+                    reply.push('\n');
+                    reply.push_str(&item.format(typed, length_prefixed));
+                }
+                reply
+            }
+        }
+    }
+}
+
+/// Wraps the write half of a connection and applies per-session reply
+/// options to everything written back to the client.
+pub struct ReplyWriter<W> {
+    inner: W,
+    // Correlation ID of the command being answered, echoed before the reply
+    pub correlation_id: Option<String>,
+    // Terminate reply lines with `\r\n` instead of `\n`
+    pub crlf: bool,
+    // Prefix replies with their type, see `Reply::format`
+    pub typed: bool,
+    // Send values as `$<len>\r\n<value>`, see `Reply::format`
+    pub length_prefixed: bool,
+}
+
+impl<W: AsyncWrite + Unpin> ReplyWriter<W> {
+    /// Creates a writer with no correlation ID set.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            correlation_id: None,
+            crlf: false,
+            typed: false,
+            length_prefixed: false,
+        }
+    }
+
+    /// Writes a complete reply formatted for the session, prefixed with
+    /// `#<id> ` when the current command carried a correlation ID and with
+    /// CRLF line endings if enabled.
+    pub async fn write_reply(&mut self, reply: &Reply) -> std::io::Result<()> {
+        let text = format!("{}\n", reply.format(self.typed, self.length_prefixed));
+        let reply = text.as_bytes();
+
+        if self.correlation_id.is_none() && !self.crlf {
+            return self.inner.write_all(reply).await;
+        }
+
+        // Build the reply in one buffer so the prefix is never split from it
+        let mut framed = match &self.correlation_id {
+            Some(id) => format!("#{} ", id).into_bytes(),
+            None => Vec::with_capacity(reply.len()),
+        };
+        for (i, byte) in reply.iter().enumerate() {
+            // Lines already ending in `\r\n` are left as they are
+            if self.crlf && *byte == b'\n' && (i == 0 || reply[i - 1] != b'\r') {
+                framed.push(b'\r');
+            }
+            framed.push(*byte);
+        }
+        self.inner.write_all(&framed).await
+    }
+
+    /// Writes a binary-safe value reply, `$<len>\r\n<bytes>` or `$-1` for a
+    /// missing value, followed by a line ending. The bytes are sent as they
+    /// are, even with CRLF line endings enabled.
+    pub async fn write_bulk(&mut self, value: Option<&[u8]>) -> std::io::Result<()> {
+        let line_ending: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+        let mut framed = match &self.correlation_id {
+            Some(id) => format!("#{} ", id).into_bytes(),
+            None => Vec::new(),
+        };
+        match value {
+            Some(bytes) => {
+                framed.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                framed.extend_from_slice(bytes);
+            }
+            None => framed.extend_from_slice(b"$-1"),
+        }
+        framed.extend_from_slice(line_ending);
+        self.inner.write_all(&framed).await
+    }
+}
+
+/// Splits an optional leading correlation ID (e.g. `#id123 GET("k")`)
+/// from a command line. Lines without the prefix are returned unchanged.
+pub fn split_correlation_id(line: &str) -> (Option<String>, &str) {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix('#') {
+        let id_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if id_len > 0 {
+            return (Some(rest[..id_len].to_string()), rest[id_len..].trim_start());
+        }
+    }
+    (None, line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_replies_are_the_bare_text() {
+        assert_eq!(Reply::ok().format(false, false), "OK");
+        assert_eq!(Reply::error("Error: boom").format(false, false), "Error: boom");
+        assert_eq!(Reply::Integer(-2).format(false, false), "-2");
+        assert_eq!(Reply::bulk("42").format(false, false), "42");
+        assert_eq!(Reply::Bulk(None).format(false, false), "(nil)");
+        assert_eq!(
+            Reply::Array(vec![Reply::bulk("a"), Reply::Bulk(None)]).format(false, false),
+            "*2\na\n(nil)"
+        );
+    }
+
+    #[test]
+    fn typed_replies_follow_the_kind_not_the_text() {
+        assert_eq!(Reply::ok().format(true, false), "+OK");
+        assert_eq!(Reply::error("Error: boom").format(true, false), "-ERR boom");
+        assert_eq!(Reply::error("Usage: GET(\"key\")").format(true, false), "-ERR Usage: GET(\"key\")");
+        assert_eq!(Reply::Integer(7).format(true, false), ":7");
+        assert_eq!(Reply::Bulk(None).format(true, false), "$-1");
+        for value in ["42", "OK", "(nil)", "Error: boom", "Usage: x", ""] {
+            assert_eq!(Reply::bulk(value).format(true, false), format!("${}", value));
+        }
+        assert_eq!(
+            Reply::Array(vec![Reply::bulk("1"), Reply::Integer(1)]).format(true, false),
+            "*2\n$1\n:1"
+        );
+    }
+
+    #[test]
+    fn length_prefixed_values_carry_their_length() {
+        assert_eq!(Reply::bulk("héllo").format(false, true), "$6\r\nhéllo");
+        assert_eq!(Reply::bulk("").format(false, true), "$0\r\n");
+        assert_eq!(Reply::Bulk(None).format(false, true), "$-1");
+        assert_eq!(Reply::bulk("42").format(true, true), "$2\r\n42");
+        // Only values are framed
+        assert_eq!(Reply::Integer(3).format(false, true), "3");
+    }
+
+    #[tokio::test]
+    async fn writer_applies_correlation_id_and_crlf() {
+        let mut out = Vec::new();
+        let mut writer = ReplyWriter::new(&mut out);
+        writer.correlation_id = Some("a1".to_string());
+        writer.crlf = true;
+        writer.typed = true;
+        writer.write_reply(&Reply::Array(vec![Reply::bulk("x")])).await.unwrap();
+        assert_eq!(out, b"#a1 *1\r\n$x\r\n");
+    }
+
+    #[test]
+    fn correlation_ids_are_split_from_the_command() {
+        assert_eq!(split_correlation_id("#id1 GET(\"k\")"), (Some("id1".to_string()), "GET(\"k\")"));
+        assert_eq!(split_correlation_id("GET(\"k\")"), (None, "GET(\"k\")"));
+        assert_eq!(split_correlation_id("# GET"), (None, "# GET"));
+    }
+}
@@ -0,0 +1,294 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+
+use crate::db::{DbInstance, DbMap};
+use crate::logger::{log_debug, log_warn};
+use crate::metrics::SharedMetrics;
+use crate::parser;
+use crate::reply;
+
+/// Most arguments a single RESP command may carry
+const MAX_ARGUMENTS: usize = 1024 * 1024;
+
+/// RESP commands that run against the selected database
+const DATABASE_COMMANDS: &[&str] = &[
+    "SET", "GET", "MGET", "MSET", "DEL", "EXISTS", "INCR", "DECR", "INCRBY", "DECRBY", "APPEND",
+    "STRLEN", "TTL", "TYPE", "DBSIZE",
+];
+
+/// A reply in RESP encoding.
+enum Reply {
+    // `+OK`
+    Simple(String),
+    // `-ERR message`
+    Error(String),
+    // `:42`
+    Integer(i64),
+    // `$<len>\r\n<bytes>`, or `$-1` for nil
+    Bulk(Option<Vec<u8>>),
+    // `*<count>` followed by the items
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK".to_string())
+    }
+
+    /// Turns a reply of the line protocol into a RESP error, e.g.
+    /// `Error: value is not an integer` into `-ERR value is not an integer`.
+    fn error(message: &str) -> Self {
+        let message = message.strip_prefix("Error: ").unwrap_or(message);
+        Reply::Error(format!("ERR {}", message))
+    }
+
+    fn bulk(bytes: impl Into<Vec<u8>>) -> Self {
+        Reply::Bulk(Some(bytes.into()))
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            // Line breaks would end the error early, so they become spaces
+            Reply::Error(e) => out.extend_from_slice(format!("-{}\r\n", e.replace(['\r', '\n'], " ")).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(Some(bytes)) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+impl From<reply::Reply> for Reply {
+    /// Encodes the reply of a statement in RESP.
+    fn from(reply: reply::Reply) -> Self {
+        match reply {
+            reply::Reply::Status(status) => Reply::Simple(status),
+            reply::Reply::Error(message) => Reply::error(&message),
+            reply::Reply::Integer(n) => Reply::Integer(n),
+            reply::Reply::Bulk(value) => Reply::Bulk(value.map(String::into_bytes)),
+            reply::Reply::Array(items) => Reply::Array(items.into_iter().map(Reply::from).collect()),
+        }
+    }
+}
+
+/// Returns true if the client's first byte is `*`, the start of a RESP
+/// array, which is how Redis clients send every command. Nothing is consumed.
+pub async fn starts_with_array<R: AsyncBufRead + Unpin>(reader: &mut R, idle_timeout: Option<Duration>) -> bool {
+    let peeked = match idle_timeout {
+        Some(limit) => timeout(limit, reader.fill_buf()).await,
+        None => Ok(reader.fill_buf().await),
+    };
+    matches!(peeked, Ok(Ok(buf)) if buf.first() == Some(&b'*'))
+}
+
+/// Reads a `<prefix><number>\r\n` header line.
+async fn read_header<R: AsyncBufRead + Unpin>(reader: &mut R, prefix: char) -> io::Result<Option<i64>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    line.trim_end()
+        .strip_prefix(prefix)
+        .and_then(|n| n.parse::<i64>().ok())
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("expected '{}', got {:?}", prefix, line.trim_end())))
+}
+
+/// Reads one command, an array of bulk strings. Returns `None` once the
+/// client has closed the connection.
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(count) = read_header(reader, '*').await? else {
+        return Ok(None);
+    };
+    if !(1..=MAX_ARGUMENTS as i64).contains(&count) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid multibulk length"));
+    }
+
+    let mut args = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = read_header(reader, '$')
+            .await?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        if !(0..=crate::MAX_BINARY_VALUE_BYTES as i64).contains(&len) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid bulk length"));
+        }
+        // The bulk string is followed by `\r\n`
+        let mut arg = vec![0; len as usize + 2];
+        reader.read_exact(&mut arg).await?;
+        arg.truncate(len as usize);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Runs `NAME("arg",...)` through the statement parser.
+fn run_statement(name: &str, args: &[&str], db: &Option<Arc<DbInstance>>) -> reply::Reply {
+    let args: Vec<String> = args.iter().map(|arg| parser::quote(arg)).collect();
+    parser::parse_statement(&format!("{}({})", name, args.join(",")), db, false)
+}
+
+/// Maps GET-style replies to a bulk string, or nil for a missing key.
+fn value_reply(key: &str, db: &Option<Arc<DbInstance>>) -> Reply {
+    match parser::get_bytes(key, db) {
+        Ok(value) => Reply::Bulk(value),
+        Err(e) => e.into(),
+    }
+}
+
+/// Serves a client speaking RESP until it disconnects or sends QUIT.
+/// Supports the string commands shared with the line protocol; databases
+/// are chosen by name with `SELECT` and must not require authentication.
+pub async fn serve<R, W>(
+    reader: &mut R,
+    mut writer: W,
+    all_dbs: &DbMap,
+    metrics: &SharedMetrics,
+    idle_timeout: Option<Duration>,
+) where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut current_db_instance: Option<Arc<DbInstance>> = None;
+    loop {
+        let read = match idle_timeout {
+            Some(limit) => timeout(limit, read_command(reader)).await,
+            None => Ok(read_command(reader).await),
+        };
+        let (reply, quit) = match read {
+            Ok(Ok(Some(args))) => {
+                metrics.commands_total.fetch_add(1, Ordering::Relaxed);
+                // A database dropped by another session is no longer selected
+                current_db_instance.take_if(|db| db.is_dropped());
+                let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+                (execute(&name, &args[1..], &mut current_db_instance, all_dbs).await, name == "QUIT")
+            }
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                (Reply::Error(format!("ERR Protocol error: {}", e)), true)
+            }
+            Ok(Err(e)) => {
+                log_warn(&format!("Error reading from socket: {}", e));
+                break;
+            }
+            Err(_) => {
+                log_debug("⏳ Closing idle connection");
+                break;
+            }
+        };
+
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+        if let Err(e) = writer.write_all(&out).await {
+            log_warn(&format!("Error writing to socket: {}", e));
+            break;
+        }
+        if quit {
+            break;
+        }
+    }
+
+    if let Some(db_instance) = current_db_instance {
+        crate::flush_blocking(db_instance).await;
+    }
+}
+
+/// Runs one RESP command and returns its reply.
+async fn execute(
+    name: &str,
+    args: &[Vec<u8>],
+    current_db_instance: &mut Option<Arc<DbInstance>>,
+    all_dbs: &DbMap,
+) -> Reply {
+    // Only the value of SET may be binary; everything else goes through the
+    // text parser
+    let mut text = Vec::with_capacity(args.len());
+    for (i, arg) in args.iter().enumerate() {
+        match std::str::from_utf8(arg) {
+            Ok(arg) => text.push(arg),
+            Err(_) if name == "SET" && args.len() == 2 && i == 1 => text.push(""),
+            Err(_) => return Reply::Error("ERR arguments must be valid UTF-8".to_string()),
+        }
+    }
+
+    if DATABASE_COMMANDS.contains(&name) && current_db_instance.is_none() {
+        return Reply::error("No database selected");
+    }
+    let db = &*current_db_instance;
+
+    match (name, text.as_slice()) {
+        ("PING", []) => Reply::Simple("PONG".to_string()),
+        ("PING", [message]) | ("ECHO", [message]) => Reply::bulk(*message),
+        ("QUIT", []) => Reply::ok(),
+        // redis-cli asks for command docs on startup; there are none to give
+        ("COMMAND", _) => Reply::Array(Vec::new()),
+        ("SELECT", [db_name]) => match crate::find_db(all_dbs, db_name).await {
+            Some(db_instance) if db_instance.require_auth => Reply::Error(format!(
+                "ERR Database '{}' requires authentication, which RESP clients can't provide",
+                db_name
+            )),
+            Some(db_instance) => {
+                // Like the line protocol, save the database being switched away from
+                if let Some(previous) = current_db_instance.take() {
+                    crate::flush_blocking(previous).await;
+                }
+                *current_db_instance = Some(db_instance);
+                Reply::ok()
+            }
+            None => Reply::Error(format!("ERR Database '{}' not found", db_name)),
+        },
+        // Binary-safe, like `SET <key> <len>` in the line protocol
+        ("SET", [key, _]) => parser::set_bytes(key, args[1].clone(), db).into(),
+        ("SET", [key, value, option, seconds]) if option.eq_ignore_ascii_case("EX") => {
+            run_statement("SET", &[key, value, &format!("{}s", seconds)], db).into()
+        }
+        ("GET", [key]) => value_reply(key, db),
+        ("MGET", keys) if !keys.is_empty() => Reply::Array(
+            keys.iter()
+                .map(|key| match value_reply(key, db) {
+                    // Like Redis, keys holding another kind of value read as nil
+                    Reply::Error(_) => Reply::Bulk(None),
+                    reply => reply,
+                })
+                .collect(),
+        ),
+        ("MSET", pairs) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            run_statement("MSET", pairs, db).into()
+        }
+        ("DEL", keys) if !keys.is_empty() => Reply::Integer(
+            keys.iter()
+                .filter(|key| run_statement("DEL", &[key], db) == reply::Reply::ok())
+                .count() as i64,
+        ),
+        ("EXISTS", keys) if !keys.is_empty() => Reply::Integer(
+            keys.iter()
+                .filter(|key| run_statement("TYPE", &[key], db) != reply::Reply::status("none"))
+                .count() as i64,
+        ),
+        ("INCR", [key]) | ("DECR", [key]) | ("STRLEN", [key]) | ("TTL", [key]) => {
+            run_statement(name, &[key], db).into()
+        }
+        ("INCRBY", [key, n]) | ("DECRBY", [key, n]) | ("APPEND", [key, n]) => {
+            run_statement(name, &[key, n], db).into()
+        }
+        ("TYPE", [key]) => run_statement("TYPE", &[key], db).into(),
+        ("DBSIZE", []) => parser::parse_statement("DBSIZE", db, false).into(),
+        _ if DATABASE_COMMANDS.contains(&name) || matches!(name, "PING" | "ECHO" | "QUIT" | "SELECT") => {
+            Reply::Error(format!("ERR wrong number of arguments for '{}' command", name.to_ascii_lowercase()))
+        }
+        _ => Reply::Error(format!("ERR unknown command '{}'", name)),
+    }
+}
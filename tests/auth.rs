@@ -0,0 +1,96 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Client, Server};
+
+/// Creates a database that requires `user` / `pass` to be used.
+fn create_with_auth(client: &mut Client, name: &str) {
+    assert_eq!(client.send(&format!("create {}", name)), "Do you want authentication (yes/no)?");
+    assert_eq!(client.send("yes"), "Enter username:");
+    assert_eq!(client.send("user"), "Enter password:");
+    assert_eq!(client.send("pass"), "Database created successfully");
+}
+
+#[test]
+fn auth_selects_a_database_in_one_line() {
+    let server = Server::start(&["--bcrypt-cost", "4"]);
+    let mut client = server.connect();
+    create_with_auth(&mut client, "locked");
+
+    assert_eq!(client.send("AUTH locked user wrong"), "Error: Authentication failed");
+    assert_eq!(client.send("AUTH locked user pass"), "OK");
+    assert_eq!(client.send(r#"SET("k","v")"#), "OK");
+    assert_eq!(client.send("AUTH missing user pass"), "Database 'missing' not found");
+}
+
+#[test]
+fn auth_without_a_name_checks_the_selected_database() {
+    let server = Server::start(&["--bcrypt-cost", "4"]);
+    let mut client = server.connect();
+    assert_eq!(client.send("AUTH user pass"), "No database selected");
+    create_with_auth(&mut client, "locked");
+    assert_eq!(client.send("AUTH locked user pass"), "OK");
+
+    assert_eq!(client.send("AUTH user pass"), "OK");
+    assert_eq!(client.send("AUTH user wrong"), "Error: Authentication failed");
+    assert_eq!(client.send(r#"GET("missing")"#), r#"Error: Key "missing" not found"#);
+    assert_eq!(client.send("HELP AUTH"), "*2");
+    assert_eq!(client.read_line(), "AUTH <dbname> <username> <password> - Authenticate against a database and select it");
+    assert_eq!(client.read_line(), "AUTH <username> <password> - Authenticate against the selected database");
+
+    // Failures count towards the same limit as the named form
+    assert_eq!(client.send("AUTH user wrong"), "Error: Authentication failed");
+    assert_eq!(client.send("AUTH user wrong"), "Too many failed authentication attempts. Disconnecting.");
+}
+
+#[test]
+fn auth_without_a_name_rejects_databases_without_credentials() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.create_and_use("open");
+    assert_eq!(
+        client.send("AUTH user pass"),
+        "Error: Database 'open' does not require authentication, use SELECT"
+    );
+}
+
+#[test]
+fn wrong_admin_passwords_are_slowed_down_and_limited() {
+    let server = Server::start_with_env(&[], &[("ADMIN_PASSWORD", "secret")]);
+    let mut client = server.connect();
+    let started = Instant::now();
+    assert_eq!(client.send("ADMIN secreT"), "Error: Invalid admin password");
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(client.send("ADMIN secret"), "OK");
+    // The success reset the count, so two more failures are allowed
+    assert_eq!(client.send("ADMIN s"), "Error: Invalid admin password");
+    assert_eq!(client.send("ADMIN secret!"), "Error: Invalid admin password");
+
+    let mut client = server.connect();
+    assert_eq!(client.send("ADMIN s"), "Error: Invalid admin password");
+    assert_eq!(client.send("ADMIN secret!"), "Error: Invalid admin password");
+    assert_eq!(client.send("ADMIN guess"), "Too many failed authentication attempts. Disconnecting.");
+    assert_eq!(client.read_raw_line(), "");
+}
+
+#[test]
+fn silent_clients_are_disconnected_at_auth_prompts() {
+    let server = Server::start(&["--bcrypt-cost", "4", "--idle-timeout", "1"]);
+    let mut client = server.connect();
+    create_with_auth(&mut client, "locked");
+    assert_eq!(client.send("use locked"), "Username:");
+    assert_eq!(client.read_line(), "Error: Idle timeout, closing connection");
+    assert_eq!(client.read_raw_line(), "");
+
+    let mut client = server.connect();
+    assert_eq!(client.send("drop locked"), "Username:");
+    assert_eq!(client.send("user"), "Password:");
+    assert_eq!(client.read_line(), "Error: Idle timeout, closing connection");
+    assert_eq!(client.read_raw_line(), "");
+
+    let mut client = server.connect();
+    assert_eq!(client.send("create other"), "Do you want authentication (yes/no)?");
+    assert_eq!(client.read_line(), "Error: Idle timeout, closing connection");
+    assert_eq!(client.read_raw_line(), "");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::Server;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+fn next_clean_counts_down_from_startup() {
+    let server = Server::start(&["--clean-interval", "30"]);
+    let mut client = server.connect();
+
+    // Scheduled before the first sweep has finished
+    let first: u64 = client.send("NEXTCLEAN").parse().unwrap();
+    assert!((29..=30).contains(&first), "{}", first);
+    sleep(Duration::from_millis(1100));
+    let second: u64 = client.send("NEXTCLEAN").parse().unwrap();
+    assert!(second < first, "{} then {}", first, second);
+}
+
+#[test]
+fn cleannow_removes_expired_keys() {
+    // Long enough that the cleaner never sweeps during the test
+    let server = Server::start_with_env(&["--clean-interval", "3600"], &[("ADMIN_PASSWORD", "secret")]);
+    let mut client = server.connect();
+    client.create_and_use("sweep");
+    assert_eq!(client.send(r#"SET("short","v","1s")"#), "OK");
+    assert_eq!(client.send(r#"SET("kept","v")"#), "OK");
+    sleep(Duration::from_millis(1200));
+
+    assert_eq!(client.send("CLEANNOW"), "Error: Admin privileges required");
+    assert_eq!(client.send("ADMIN secret"), "OK");
+    assert_eq!(client.send("CLEANNOW"), "1");
+    assert_eq!(client.send("CLEANNOW"), "0");
+    assert_eq!(client.send("DBSIZE"), "1");
+    assert_eq!(client.send(r#"GET("kept")"#), "v");
+}
+
+#[test]
+fn keys_survive_while_expiry_is_paused() {
+    let server = Server::start_with_env(&["--clean-interval", "3600"], &[("ADMIN_PASSWORD", "secret")]);
+    let mut client = server.connect();
+    client.create_and_use("paused");
+    assert_eq!(client.send("PAUSEEXPIRY 30"), "Error: Admin privileges required");
+    assert_eq!(client.send("ADMIN secret"), "OK");
+    let too_long = "Error: Pause too long (at most 86400 seconds)";
+    assert_eq!(client.send("PAUSEEXPIRY 86401"), too_long);
+    assert_eq!(client.send("PAUSEEXPIRY 18446744073709551615"), too_long);
+    assert_eq!(client.send("PAUSEEXPIRY 30"), "OK");
+    assert_eq!(client.send(r#"SET("short","v","1s")"#), "OK");
+    sleep(Duration::from_millis(1200));
+
+    // Neither reads nor sweeps treat the key as expired
+    assert_eq!(client.send(r#"GET("short")"#), "v");
+    assert_eq!(client.send("CLEANNOW"), "0");
+
+    // Ending the pause lets it expire
+    assert_eq!(client.send("PAUSEEXPIRY 0"), "OK");
+    assert_eq!(client.send("CLEANNOW"), "1");
+    assert!(client.send(r#"GET("short")"#).starts_with("Error: Key"));
+}
@@ -0,0 +1,121 @@
+//! Runs the server binary for integration tests and talks to it over TCP.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// A server running on a free port with a data directory of its own,
+/// killed and cleaned up when dropped.
+pub struct Server {
+    child: Child,
+    port: u16,
+    data_dir: PathBuf,
+}
+
+impl Server {
+    /// Starts the server with `args` and waits until it accepts connections.
+    pub fn start(args: &[&str]) -> Self {
+        Self::start_with_env(args, &[])
+    }
+
+    /// Starts the server with `args` and extra environment variables.
+    pub fn start_with_env(args: &[&str], env: &[(&str, &str)]) -> Self {
+        // Any port the OS hands out is free for long enough to reuse
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let data_dir = std::env::temp_dir().join(format!("db-server-it-{}-{}", std::process::id(), port));
+        let child = Command::new(env!("CARGO_BIN_EXE_db-server"))
+            .arg(port.to_string())
+            .arg("--data-dir")
+            .arg(&data_dir)
+            // Logs go to the discarded stdout rather than a file in the repo
+            .args(["--log-file", "-"])
+            .args(args)
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("server starts");
+
+        let started = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "server didn't start");
+            sleep(Duration::from_millis(20));
+        }
+        Self { child, port, data_dir }
+    }
+
+    /// Opens a new connection.
+    pub fn connect(&self) -> Client {
+        let stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Sends the server SIGHUP.
+    #[cfg(unix)]
+    pub fn hang_up(&self) {
+        let status = Command::new("kill")
+            .args(["-HUP", &self.child.id().to_string()])
+            .status()
+            .expect("kill runs");
+        assert!(status.success());
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+/// A connection speaking the line protocol.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    /// Sends one line and returns the first line of the reply.
+    pub fn send(&mut self, line: &str) -> String {
+        self.send_only(line);
+        self.read_line()
+    }
+
+    /// Reads the next reply line, without its line ending.
+    pub fn read_line(&mut self) -> String {
+        self.read_raw_line().trim_end_matches(['\r', '\n']).to_string()
+    }
+
+    /// Reads the next reply line along with its line ending.
+    pub fn read_raw_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    /// Sends one line without reading a reply.
+    pub fn send_only(&mut self, line: &str) {
+        self.writer.write_all(format!("{}\n", line).as_bytes()).unwrap();
+    }
+
+    /// Creates a database without authentication and selects it.
+    pub fn create_and_use(&mut self, name: &str) {
+        assert_eq!(self.send(&format!("create {}", name)), "Do you want authentication (yes/no)?");
+        assert_eq!(self.send("no"), "Database created successfully");
+        assert_eq!(self.send(&format!("use {}", name)), format!("Using database '{}'", name));
+    }
+}
@@ -0,0 +1,46 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::Server;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[test]
+fn sighup_reloads_the_clean_interval_and_log_level() {
+    let dir = std::env::temp_dir().join(format!("db-server-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("server.conf");
+    let log_path = dir.join("server.log");
+    std::fs::write(&config, "CLEAN_INTERVAL=30\nLOG_LEVEL=warn\n").unwrap();
+
+    let server = Server::start(&["--config", config.to_str().unwrap(), "--log-file", log_path.to_str().unwrap()]);
+    let mut client = server.connect();
+    let before: u64 = client.send("NEXTCLEAN").parse().unwrap();
+    assert!((29..=30).contains(&before), "{}", before);
+
+    std::fs::write(&config, "CLEAN_INTERVAL=600\nLOG_LEVEL=info\nMAX_KEYS=10\n").unwrap();
+    server.hang_up();
+
+    // The signal is handled asynchronously, and the reload is logged last
+    let started = Instant::now();
+    let log = loop {
+        // Nothing is written, so the file isn't created, until the level changes
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        if log.contains("Reloaded config file") || started.elapsed() > Duration::from_secs(5) {
+            break log;
+        }
+        sleep(Duration::from_millis(50));
+    };
+    let after: u64 = client.send("NEXTCLEAN").parse().unwrap();
+    assert!((599..=600).contains(&after), "{}", after);
+    assert_eq!(client.send("INFO"), "*5");
+    let info: Vec<String> = (0..5).map(|_| client.read_line()).collect();
+    assert!(info.contains(&"clean_interval_seconds:600".to_string()), "{:?}", info);
+
+    // Info entries are only written once the new level applies
+    assert!(!log.contains("Log file reopened"), "{}", log);
+    assert!(log.contains("Reloaded config file"), "{}", log);
+    assert!(log.contains("MAX_KEYS changed in the config file but needs a restart"), "{}", log);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn databases_are_created_used_and_dropped() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+
+    client.create_and_use("inventory");
+    assert_eq!(client.send(r#"SET("k","v")"#), "OK");
+    assert_eq!(client.send("create inventory"), "Cannot create a database. A database is already selected.");
+    assert_eq!(client.send("QUIT"), "BYE");
+
+    // A new session finds the saved database and its keys
+    let mut client = server.connect();
+    assert_eq!(client.send("create inventory"), "Error: Database 'inventory' already exists");
+    assert_eq!(client.send("use inventory"), "Using database 'inventory'");
+    assert_eq!(client.send(r#"GET("k")"#), "v");
+    assert!(client.send("drop inventory").starts_with("Cannot drop the currently selected database"));
+
+    let mut other = server.connect();
+    assert_eq!(other.send("drop inventory"), "Database 'inventory' deleted successfully");
+    assert!(!server.data_dir().join("inventory.json").exists());
+    assert_eq!(other.send("use inventory"), "Database 'inventory' not found");
+}
+
+#[test]
+fn names_that_could_leave_the_data_directory_are_rejected() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    let overlong = "x".repeat(65);
+    for name in ["../x", "a/b", overlong.as_str()] {
+        for command in ["create", "use", "drop"] {
+            let reply = client.send(&format!("{} {}", command, name));
+            assert!(reply.starts_with("Error: Invalid database name"), "{} {}: {}", command, name, reply);
+        }
+    }
+}
+
+#[test]
+fn sessions_on_the_same_database_see_each_others_writes() {
+    let server = Server::start(&["--clean-interval", "1"]);
+    let mut a = server.connect();
+    let mut b = server.connect();
+    a.create_and_use("shared");
+    assert_eq!(b.send("use shared"), "Using database 'shared'");
+
+    assert_eq!(a.send(r#"SET("k","v")"#), "OK");
+    assert_eq!(b.send(r#"GET("k")"#), "v");
+    assert_eq!(b.send(r#"DEL("k")"#), "OK");
+    assert_eq!(a.send(r#"GET("k")"#), r#"Error: Key "k" not found"#);
+
+    // Still shared once the cleaner has swept the database
+    let mut events = server.connect();
+    assert_eq!(events.send("SUBSCRIBE expired"), "Subscribed to expired");
+    assert_eq!(a.send(r#"SET("short","v","1s")"#), "OK");
+    assert_eq!(events.read_line(), "expired shared short");
+    assert_eq!(b.send(r#"GET("short")"#), r#"Error: Key "short" not found"#);
+    assert_eq!(b.send(r#"SET("k","w")"#), "OK");
+    assert_eq!(a.send(r#"GET("k")"#), "w");
+    assert_eq!(a.send(r#"DEL("k")"#), "OK");
+    assert_eq!(b.send(r#"GET("k")"#), r#"Error: Key "k" not found"#);
+}
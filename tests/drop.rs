@@ -0,0 +1,22 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn dropping_a_database_in_use_elsewhere_keeps_its_file_gone() {
+    let server = Server::start(&[]);
+    let mut user = server.connect();
+    let mut dropper = server.connect();
+
+    user.create_and_use("shared");
+    assert_eq!(user.send(r#"SET("k","v")"#), "OK");
+    assert_eq!(dropper.send("drop shared"), "Database 'shared' deleted successfully");
+    let path = server.data_dir().join("shared.json");
+    assert!(!path.exists());
+
+    // The other session is deselected rather than saving the database again
+    assert!(user.send(r#"SET("k2","v")"#).starts_with("No database selected"));
+    assert_eq!(user.send("QUIT"), "BYE");
+    assert!(!path.exists());
+    assert_eq!(dropper.send("use shared"), "Database 'shared' not found");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn length_prefixed_values_tell_empty_from_missing() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.create_and_use("lengths");
+    assert_eq!(client.send(r#"SET("empty","")"#), "OK");
+    assert_eq!(client.send(r#"SET("text","a b")"#), "OK");
+
+    assert_eq!(client.send("PROTOCOL lenprefix"), "OK");
+    assert_eq!(client.send(r#"GET("empty")"#), "$0");
+    assert_eq!(client.read_line(), "");
+    assert_eq!(client.send(r#"GET("missing")"#), "$-1");
+    assert_eq!(client.send(r#"GET("text")"#), "$3");
+    assert_eq!(client.read_line(), "a b");
+
+    // Statuses and counts are not framed
+    assert_eq!(client.send(r#"STRLEN("text")"#), "3");
+    assert_eq!(client.send("PROTOCOL line"), "OK");
+    assert!(client.send(r#"GET("missing")"#).starts_with("Error: Key"));
+}
+
+#[test]
+fn replies_echo_the_correlation_id_of_their_command() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.create_and_use("traced");
+
+    assert_eq!(client.send(r#"#req-1 SET("k","v")"#), "#req-1 OK");
+    assert_eq!(client.send(r#"#req-2 GET("k")"#), "#req-2 v");
+    assert_eq!(client.send(r#"#req-3 GET("missing")"#), "#req-3 Error: Key \"missing\" not found");
+    // The ID only applies to its own command
+    assert_eq!(client.send(r#"GET("k")"#), "v");
+    assert_eq!(client.send("#ping ECHO hi"), "#ping hi");
+}
+
+#[test]
+fn crlf_line_endings_apply_to_every_reply_line() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.create_and_use("endings");
+    assert_eq!(client.send(r#"RPUSH("l","a","b")"#), "2");
+
+    client.send_only("PROTOCOL crlf");
+    assert_eq!(client.read_raw_line(), "OK\r\n");
+    client.send_only(r#"LRANGE("l","0","-1")"#);
+    assert_eq!(client.read_raw_line(), "*2\r\n");
+    assert_eq!(client.read_raw_line(), "a\r\n");
+    assert_eq!(client.read_raw_line(), "b\r\n");
+    // Length-prefixed values keep a single CRLF after the length
+    client.send_only("PROTOCOL lenprefix");
+    assert_eq!(client.read_raw_line(), "OK\r\n");
+    client.send_only(r#"LPOP("l")"#);
+    assert_eq!(client.read_raw_line(), "$1\r\n");
+    assert_eq!(client.read_raw_line(), "a\r\n");
+
+    client.send_only("PROTOCOL lf");
+    assert_eq!(client.read_raw_line(), "OK\n");
+}
+
+#[test]
+fn getrange_with_spaces_replies_with_raw_bytes() {
+    let server = Server::start(&[]);
+    let mut client = server.connect();
+    client.create_and_use("ranges");
+    assert_eq!(client.send(r#"SET("k","héllo")"#), "OK");
+
+    assert_eq!(client.send("GETRANGE k 1 2"), "$2");
+    assert_eq!(client.read_line(), "é");
+    assert_eq!(client.send("GETRANGE k 10 20"), "$0");
+    assert_eq!(client.read_line(), "");
+    assert!(client.send(r#"GETRANGE("k","0","1")"#).starts_with("Error: Offset splits a UTF-8 character"));
+}